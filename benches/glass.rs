@@ -0,0 +1,143 @@
+// Collision and row clearing of the bit board against the cell array the
+// glass used to be. Run with `cargo bench`
+use rustris::board::Board;
+use rustris::random::Random;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const WIDTH: usize = 10;
+const HEIGHT: usize = 20;
+const PLACEMENTS: usize = 1_000_000;
+const CLEARS: usize = 100_000;
+
+// Tetrominoes in their spawn rotation as cells and as row masks
+const PIECES: [[(usize, usize); 4]; 7] = [
+    [(0, 0), (1, 0), (2, 0), (3, 0)],
+    [(0, 0), (0, 1), (1, 1), (2, 1)],
+    [(2, 0), (0, 1), (1, 1), (2, 1)],
+    [(0, 0), (1, 0), (0, 1), (1, 1)],
+    [(1, 0), (2, 0), (0, 1), (1, 1)],
+    [(0, 0), (1, 0), (2, 0), (1, 1)],
+    [(0, 0), (1, 0), (1, 1), (2, 1)]
+];
+
+// The glass as it was stored before: a cell array scanned whole for
+// every cell of the piece
+struct ArrayGlass {
+    cells: [[bool; WIDTH]; HEIGHT]
+}
+
+impl ArrayGlass {
+    fn fits(&self, cells: &[(usize, usize)]) -> bool {
+        if cells.iter().any(|(x, y)| *x >= WIDTH || *y >= HEIGHT) {
+            return false;
+        }
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                if self.cells[y][x] && cells.contains(&(x, y)) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn clear_full_rows(&mut self) -> u32 {
+        let mut new_cells = [[false; WIDTH]; HEIGHT];
+        let mut row_idx = HEIGHT;
+        for y in (0..HEIGHT).rev() {
+            if !self.cells[y].iter().all(|cell| *cell) {
+                row_idx -= 1;
+                new_cells[row_idx] = self.cells[y];
+            }
+        }
+        self.cells = new_cells;
+        row_idx as u32
+    }
+}
+
+fn piece_rows(piece: &[(usize, usize)]) -> Vec<u64> {
+    let mut rows = vec![0; piece.iter().map(|(_, y)| y + 1).max().unwrap_or(0)];
+    for (x, y) in piece {
+        rows[*y] |= 1 << x;
+    }
+    rows
+}
+
+// Bottom half of the glass filled at random, every row with a hole
+fn random_glass(random: &mut Random) -> (ArrayGlass, Board) {
+    let mut array = ArrayGlass {cells: [[false; WIDTH]; HEIGHT]};
+    let mut board = Board::new(WIDTH, HEIGHT);
+    for y in HEIGHT / 2..HEIGHT {
+        let hole = random.below(WIDTH);
+        for x in 0..WIDTH {
+            if x != hole && random.below(3) != 0 {
+                array.cells[y][x] = true;
+                board.fill(x, y);
+            }
+        }
+    }
+    (array, board)
+}
+
+fn report(name: &str, operations: usize, elapsed: Duration) {
+    println!("{:<24} {:>10.2?} {:>8.1} ns/op", name, elapsed, elapsed.as_nanos() as f64 / operations as f64);
+}
+
+fn main() {
+    let mut random = Random::new(1);
+    let (mut array, mut board) = random_glass(&mut random);
+    let placements: Vec<(usize, usize, usize)> = (0..PLACEMENTS)
+        .map(|_| (random.below(PIECES.len()), random.below(WIDTH), random.below(HEIGHT)))
+        .collect();
+    let cells: Vec<Vec<(usize, usize)>> = placements.iter()
+        .map(|(piece, x, y)| PIECES[*piece].iter().map(|(cell_x, cell_y)| (x + cell_x, y + cell_y)).collect())
+        .collect();
+    let masks: Vec<Vec<u64>> = PIECES.iter().map(|piece| piece_rows(piece)).collect();
+
+    let start = Instant::now();
+    let array_fits = cells.iter().filter(|cells| array.fits(black_box(cells))).count();
+    let array_time = start.elapsed();
+
+    let start = Instant::now();
+    let board_fits = cells.iter().filter(|cells| board.fits(black_box(cells))).count();
+    let board_time = start.elapsed();
+
+    let start = Instant::now();
+    let rows_fits = placements.iter()
+        .filter(|(piece, x, y)| board.fits_rows(black_box(&masks[*piece]), *x, *y))
+        .count();
+    let rows_time = start.elapsed();
+
+    assert_eq!(array_fits, board_fits);
+    assert_eq!(array_fits, rows_fits);
+    println!("{} placements, {} fit", PLACEMENTS, array_fits);
+    report("array fits", PLACEMENTS, array_time);
+    report("board fits (cells)", PLACEMENTS, board_time);
+    report("board fits (row masks)", PLACEMENTS, rows_time);
+
+    // Two full rows are cleared from a copy of the glass every time
+    for y in [HEIGHT - 1, HEIGHT - 3] {
+        for x in 0..WIDTH {
+            array.cells[y][x] = true;
+            board.fill(x, y);
+        }
+    }
+    let start = Instant::now();
+    for _ in 0..CLEARS {
+        let mut copy = ArrayGlass {cells: array.cells};
+        assert_eq!(black_box(copy.clear_full_rows()), 2);
+    }
+    let array_time = start.elapsed();
+
+    let mut copy = board.clone();
+    let start = Instant::now();
+    for _ in 0..CLEARS {
+        copy.clone_from(&board);
+        assert_eq!(black_box(copy.clear_full_rows()), 2);
+    }
+    let board_time = start.elapsed();
+
+    report("array clear rows", CLEARS, array_time);
+    report("board clear rows", CLEARS, board_time);
+}
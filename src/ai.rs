@@ -0,0 +1,285 @@
+// Computer player: tries every placement of the falling piece, scores the
+// glass each one would leave and plays the best one. The game uses it for
+// the demo, play() runs whole games without a screen
+use crate::board::Board;
+use crate::piece::{Piece, PieceSet};
+use crate::placement::{self, Move, Placement};
+use crate::random::Random;
+use crate::settings::Action;
+use crate::storage;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Weights tuned by rustris-tune, the demo plays with them when the file is there
+const WEIGHTS_FILE: &str = "ai.cfg";
+const HEADER: &str = "# rustris ai weights";
+pub const WEIGHT_COUNT: usize = 5;
+const WEIGHT_NAMES: [&str; WEIGHT_COUNT] = ["aggregate_height", "holes", "bumpiness", "lines", "wells"];
+
+// Multipliers of the glass features, the score of a placement is their sum.
+// Features which make the glass worse get negative weights
+#[derive(Clone, Copy, PartialEq)]
+pub struct Weights {
+    // Sum of the column heights
+    pub aggregate_height: f64,
+    // Empty cells with a filled one somewhere above them
+    pub holes: f64,
+    // Sum of height differences of neighbouring columns
+    pub bumpiness: f64,
+    // Rows the placement clears
+    pub lines: f64,
+    // Sum of the depths of columns lower than both neighbours
+    pub wells: f64
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {aggregate_height: -0.51, holes: -0.36, bumpiness: -0.18, lines: 0.76, wells: -0.1}
+    }
+}
+
+impl Weights {
+    // In the order of WEIGHT_NAMES
+    pub fn to_array(&self) -> [f64; WEIGHT_COUNT] {
+        [self.aggregate_height, self.holes, self.bumpiness, self.lines, self.wells]
+    }
+
+    pub fn from_array(values: [f64; WEIGHT_COUNT]) -> Self {
+        let [aggregate_height, holes, bumpiness, lines, wells] = values;
+        Weights {aggregate_height, holes, bumpiness, lines, wells}
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        storage::config_dir().map(|dir| dir.join(WEIGHTS_FILE))
+    }
+
+    // Weights missing from the file keep their defaults
+    pub fn load_from(path: &Path) -> Option<Self> {
+        let text = fs::read_to_string(path).ok()?;
+        let mut values = Self::default().to_array();
+        for (name, value) in storage::parse_key_values(&text) {
+            if let Some(idx) = WEIGHT_NAMES.iter().position(|known| *known == name) {
+                values[idx] = value.parse().ok()?;
+            }
+        }
+        Some(Self::from_array(values))
+    }
+
+    // Tuned weights if there are any, the defaults otherwise
+    pub fn load() -> Self {
+        Self::path().and_then(|path| Self::load_from(&path)).unwrap_or_default()
+    }
+
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let mut text = format!("{}\n", HEADER);
+        for (name, value) in WEIGHT_NAMES.iter().zip(self.to_array()) {
+            text += &format!("{} = {}\n", name, value);
+        }
+        storage::write_atomic(path, text.as_bytes())
+    }
+}
+
+pub fn evaluate(board: &Board, lines: u32, weights: &Weights) -> f64 {
+    let heights: Vec<usize> = (0..board.width())
+        .map(|x| (0..board.height()).find(|y| board.is_filled(x, *y)).map_or(0, |top| board.height() - top))
+        .collect();
+    let aggregate_height: usize = heights.iter().sum();
+    let holes: usize = (0..board.width())
+        .map(|x| (board.height() - heights[x]..board.height()).filter(|y| !board.is_filled(x, *y)).count())
+        .sum();
+    let bumpiness: usize = heights.windows(2).map(|pair| pair[0].abs_diff(pair[1])).sum();
+    // Walls count as high neighbours
+    let wells: usize = (0..heights.len()).map(|x| {
+        let left = if x == 0 {usize::MAX} else {heights[x - 1]};
+        let right = heights.get(x + 1).copied().unwrap_or(usize::MAX);
+        left.min(right).saturating_sub(heights[x]).min(board.height())
+    }).sum();
+
+    weights.aggregate_height * aggregate_height as f64
+        + weights.holes * holes as f64
+        + weights.bumpiness * bumpiness as f64
+        + weights.lines * lines as f64
+        + weights.wells * wells as f64
+}
+
+// Glass left by the placement and the rows it cleared
+pub fn place(board: &Board, piece: &Piece, placement: &Placement) -> (Board, u32) {
+    let mut board = board.clone();
+    for (x, y) in piece.cells(placement.rotation, placement.x, placement.y) {
+        board.fill(x, y);
+    }
+    let lines = board.clear_full_rows();
+    (board, lines)
+}
+
+// The first of the equally good ones wins, which is the one with the
+// shortest path, and the choice is deterministic
+pub fn best_placement(board: &Board, piece: &Piece, position: (usize, usize, usize),
+                      weights: &Weights) -> Option<Placement> {
+    let mut best: Option<(f64, Placement)> = None;
+    for placement in placement::reachable_placements(board, piece, position) {
+        let (after, lines) = place(board, piece, &placement);
+        let score = evaluate(&after, lines, weights);
+        if best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, placement));
+        }
+    }
+    best.map(|(_, placement)| placement)
+}
+
+// Plays the game through its actions, like a player at the keyboard
+pub struct Bot {
+    weights: Weights,
+    // Logic frames between two actions, 0 sends the whole path at once
+    step_frames: u32,
+    wait: u32,
+    // Piece number and the rotation and position it locks at
+    target: Option<(u32, usize, usize, usize)>
+}
+
+impl Bot {
+    pub fn new(weights: Weights, step_frames: u32) -> Self {
+        Bot {weights, step_frames, wait: step_frames, target: None}
+    }
+
+    // Actions for this logic frame. The path is looked up again every time,
+    // gravity moves the piece in between
+    pub fn actions(&mut self, board: &Board, piece: &Piece, position: (usize, usize, usize),
+                   piece_number: u32) -> Vec<Action> {
+        if self.wait > 0 {
+            self.wait -= 1;
+            return Vec::new();
+        }
+
+        let planned = match self.target {
+            Some((number, rotation, x, y)) if number == piece_number => {
+                placement::reachable_placements(board, piece, position).into_iter()
+                    .find(|placement| (placement.rotation, placement.x, placement.y) == (rotation, x, y))
+            }
+            _ => None
+        };
+        let placement = match planned.or_else(|| best_placement(board, piece, position, &self.weights)) {
+            Some(placement) => placement,
+            None => return vec![Action::Drop]
+        };
+        self.target = Some((piece_number, placement.rotation, placement.x, placement.y));
+
+        // Moves up to the first fall are made now, the piece is dropped
+        // right after the last one
+        let limit = if self.step_frames == 0 {placement.moves.len()} else {1};
+        let mut actions: Vec<Action> = placement.moves.iter().take(limit).map_while(Move::action).collect();
+        if actions.len() == placement.moves.len() {
+            actions.push(Action::Drop);
+        }
+        // Waiting for gravity is no move, it is checked again next frame
+        if !actions.is_empty() {
+            self.wait = self.step_frames;
+        }
+        actions
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct GameStats {
+    pub pieces: u32,
+    pub lines: u32
+}
+
+// Plays a game without gravity or a screen until the glass overflows or
+// the given number of pieces is placed. Pieces come in the same order as in
+// a game with the same seed
+pub fn play(pieces: &PieceSet, glass_size: (usize, usize), seed: u64, weights: &Weights,
+            max_pieces: u32) -> GameStats {
+    let (width, height) = glass_size;
+    let mut board = Board::new(width, height);
+    let mut random = Random::new(seed);
+    let mut stats = GameStats {pieces: 0, lines: 0};
+    while stats.pieces < max_pieces {
+        let piece = &pieces.pieces[pieces.random_piece(&mut random)];
+        let rotation = random.below(piece.rotation_count());
+        let x = piece.spawn_x(rotation, width);
+        if !board.fits_rows(piece.rows(rotation), x, 0) {
+            break;
+        }
+        let placement = match best_placement(&board, piece, (rotation, x, 0), weights) {
+            Some(placement) => placement,
+            None => break
+        };
+        let (after, lines) = place(&board, piece, &placement);
+        board = after;
+        stats.pieces += 1;
+        stats.lines += lines;
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(width: usize, height: usize, cells: &[(usize, usize)]) -> Board {
+        let mut board = Board::new(width, height);
+        for (x, y) in cells {
+            board.fill(*x, *y);
+        }
+        board
+    }
+
+    fn piece(name: &str) -> Piece {
+        let pieces = PieceSet::standard();
+        pieces.pieces[pieces.piece_index(name).unwrap()].clone()
+    }
+
+    // Rows 6 to 9 full but for the last column
+    fn well_board() -> Board {
+        let cells: Vec<(usize, usize)> = (6..10).flat_map(|y| (0..3).map(move |x| (x, y))).collect();
+        board(4, 10, &cells)
+    }
+
+    #[test]
+    fn holes_and_height_lower_the_score() {
+        let weights = Weights::default();
+        let empty = evaluate(&board(4, 10, &[]), 0, &weights);
+        let low = evaluate(&board(4, 10, &[(0, 9)]), 0, &weights);
+        assert!(low < empty);
+        // Same heights, but the second glass has a hole under the top cell
+        let solid = evaluate(&board(4, 10, &[(0, 8), (0, 9)]), 0, &weights);
+        let holed = evaluate(&board(4, 10, &[(0, 8)]), 0, &weights);
+        assert!(holed < solid);
+    }
+
+    #[test]
+    fn open_line_clears_are_taken() {
+        let board = well_board();
+        let i = piece("I");
+        let placement = best_placement(&board, &i, (0, 1, 0), &Weights::default()).unwrap();
+        assert_eq!((placement.rotation, placement.x, placement.y), (0, 3, 6));
+        assert_eq!(place(&board, &i, &placement).1, 4);
+    }
+
+    #[test]
+    fn bots_send_the_path_and_drop() {
+        let board = well_board();
+        let i = piece("I");
+        let mut bot = Bot::new(Weights::default(), 0);
+        assert!(bot.actions(&board, &i, (0, 1, 0), 1) == [Action::Right, Action::Right, Action::Drop]);
+
+        // One move every third frame
+        let mut bot = Bot::new(Weights::default(), 2);
+        assert!(bot.actions(&board, &i, (0, 1, 0), 1).is_empty());
+        assert!(bot.actions(&board, &i, (0, 1, 0), 1).is_empty());
+        assert!(bot.actions(&board, &i, (0, 1, 0), 1) == [Action::Right]);
+        assert!(bot.actions(&board, &i, (0, 2, 0), 1).is_empty());
+    }
+
+    #[test]
+    fn games_follow_the_seed() {
+        let pieces = PieceSet::standard();
+        let weights = Weights::default();
+        let stats = play(&pieces, (10, 20), 7, &weights, 200);
+        assert!(play(&pieces, (10, 20), 7, &weights, 200) == stats);
+        assert_eq!(stats.pieces, 200);
+        assert!(stats.lines > 0);
+    }
+}
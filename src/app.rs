@@ -0,0 +1,307 @@
+use crate::game::{Game, GAME_CANVAS_HEIGHT, GAME_CANVAS_WIDTH};
+use crate::ioscreen::{self, Canvas, Key};
+use crate::menu::{self, Menu, MenuEvent};
+use crate::mode::{GameMode, GAME_MODES};
+use crate::settings::{Action, Settings, SettingsScreen, ACTIONS};
+use crate::highscores::HighScores;
+use crate::replay::{LoadError, Replay};
+use crate::savegame::SavedGame;
+use crate::spectate::Broadcast;
+use crate::storage;
+use crate::versus::{Opponent, Versus};
+use crate::i18n::{tr, tr_args, Msg};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// How long the menus wait for a key before redrawing
+const MENU_POLL_MSEC: u64 = 100;
+// The demo starts by itself after the main menu sat this long untouched
+const DEMO_IDLE_SECS: u64 = 30;
+const DEMO_MODE: GameMode = GameMode::Endless;
+
+const LOGO: [&str; 3] = [
+    "█▀▄ █ █ █▀▀ ▀█▀ █▀▄ █ █▀▀",
+    "█▀▄ █ █ ▀▀█  █  █▀▄ █ ▀▀█",
+    "▀ ▀ ▀▀▀ ▀▀▀  ▀  ▀ ▀ ▀ ▀▀▀"
+];
+
+// Continue is put in front of these when there is a saved game
+const MAIN_ITEMS: [Msg; 9] = [
+    Msg::Play, Msg::Versus, Msg::TwoPlayers, Msg::HighScores, Msg::Replays, Msg::Demo, Msg::Settings, Msg::Controls, Msg::Quit
+];
+
+enum Screen {
+    // Menu and the items it shows
+    MainMenu(Menu, Vec<Msg>),
+    ModeSelect(Menu),
+    // Index of the game mode shown and the loaded tables
+    HighScores(usize, HighScores),
+    // Saved replays, the newest first
+    Replays(Menu, Vec<PathBuf>),
+    Settings(SettingsScreen),
+    Controls,
+    Play(GameMode),
+    Continue(SavedGame),
+    Replay(Replay),
+    Demo,
+    Versus(Opponent)
+}
+
+pub struct App {
+    screen: Screen,
+    settings: Settings,
+    canvas: Canvas,
+    // Last key press, the demo starts after a while without one
+    idle_since: Instant,
+    // Spectators watching the games played
+    broadcast: Option<Broadcast>
+}
+
+impl App {
+    pub fn new(settings: Settings) -> Self {
+        App {screen: Self::main_menu(Msg::Continue), settings, canvas: Self::new_canvas(), idle_since: Instant::now(), broadcast: None}
+    }
+
+    // Skips the menus and starts a game of the given mode right away
+    pub fn new_game(settings: Settings, mode: GameMode) -> Self {
+        App {screen: Screen::Play(mode), settings, canvas: Self::new_canvas(), idle_since: Instant::now(), broadcast: None}
+    }
+
+    // Skips the menus and plays the replay right away
+    pub fn new_replay(settings: Settings, replay: Replay) -> Self {
+        App {screen: Screen::Replay(replay), settings, canvas: Self::new_canvas(), idle_since: Instant::now(), broadcast: None}
+    }
+
+    pub fn set_broadcast(&mut self, broadcast: Option<Broadcast>) {
+        self.broadcast = broadcast;
+    }
+
+    fn new_canvas() -> Canvas {
+        let (width, height) = ioscreen::screen_size().unwrap_or((GAME_CANVAS_WIDTH, GAME_CANVAS_HEIGHT));
+        Canvas::new(width, height)
+    }
+
+    // The selected item is the given one if it is shown, the first one otherwise
+    fn main_menu(selected: Msg) -> Screen {
+        let mut items = MAIN_ITEMS.to_vec();
+        if SavedGame::load().is_some() {
+            items.insert(0, Msg::Continue);
+        }
+        let mut menu = Menu::new(tr(Msg::MainMenu), items.iter().map(|item| tr(*item).to_string()).collect());
+        menu.selected = items.iter().position(|item| *item == selected).unwrap_or(0);
+        Screen::MainMenu(menu, items)
+    }
+
+    fn mode_select() -> Screen {
+        let mut items: Vec<String> = GAME_MODES.iter().map(|mode| mode.title().to_string()).collect();
+        items.push(tr(Msg::Back).to_string());
+        Screen::ModeSelect(Menu::new(tr(Msg::GameMode), items))
+    }
+
+    fn replays() -> Screen {
+        let paths = Replay::list();
+        let mut items: Vec<String> = paths.iter().map(|path| {
+            let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            match Replay::load(path) {
+                Ok(replay) => format!("{} {} {}", storage::format_date(replay.date), replay.mode.title(), replay.points),
+                Err(LoadError::PiecesChanged) => format!("{} {}", file_name, tr(Msg::PiecesChanged)),
+                Err(LoadError::Unreadable) => file_name
+            }
+        }).collect();
+        items.push(tr(Msg::Back).to_string());
+        let title = if paths.is_empty() {Msg::NoReplays} else {Msg::Replays};
+        Screen::Replays(Menu::new(tr(title), items), paths)
+    }
+
+    pub fn run(&mut self) {
+        loop {
+            if ioscreen::quit_requested() {
+                return;
+            }
+
+            let game = match &self.screen {
+                Screen::Play(mode) => Some((Game::new(*mode, self.settings.clone()), Msg::Play)),
+                Screen::Continue(saved) => {
+                    SavedGame::delete();
+                    Game::from_saved(saved.clone(), self.settings.clone()).map(|game| (game, Msg::Continue))
+                }
+                Screen::Replay(replay) => Some((Game::from_replay(replay.clone(), self.settings.clone()), Msg::Replays)),
+                Screen::Demo => Some((Game::demo(DEMO_MODE, self.settings.clone()), Msg::Demo)),
+                _ => None
+            };
+            let played = match (game, &self.screen) {
+                (Some((mut game, menu_item)), _) => {
+                    game.set_broadcast(self.broadcast.take());
+                    game.game_loop();
+                    game.save_unfinished();
+                    self.broadcast = game.take_broadcast();
+                    self.settings = game.into_settings();
+                    Some(menu_item)
+                }
+                (None, Screen::Versus(opponent)) => {
+                    let mut versus = Versus::new(*opponent, self.settings.clone());
+                    versus.set_broadcast(self.broadcast.take());
+                    versus.game_loop();
+                    self.broadcast = versus.take_broadcast();
+                    Some(if *opponent == Opponent::Cpu {Msg::Versus} else {Msg::TwoPlayers})
+                }
+                _ => None
+            };
+            if let Some(menu_item) = played {
+                // The game may have seen resizes this canvas has missed
+                self.canvas = Self::new_canvas();
+                ioscreen::clear_screen();
+                self.screen = Self::main_menu(menu_item);
+                self.idle_since = Instant::now();
+                continue;
+            } else if let Screen::Continue(_) = self.screen {
+                // The save does not fit this version of the game
+                self.screen = Self::main_menu(Msg::Play);
+            }
+
+            if let Screen::MainMenu(_, _) = self.screen {
+                if self.idle_since.elapsed() >= Duration::from_secs(DEMO_IDLE_SECS) {
+                    self.screen = Screen::Demo;
+                    continue;
+                }
+            }
+
+            if ioscreen::take_resize().is_some() {
+                self.canvas = Self::new_canvas();
+                ioscreen::clear_screen();
+            }
+            self.draw();
+            if let Some(broadcast) = &mut self.broadcast {
+                broadcast.idle();
+            }
+
+            if let Some(key) = ioscreen::getch(Duration::from_millis(MENU_POLL_MSEC)) {
+                self.idle_since = Instant::now();
+                if !self.handle_key(key) {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Returns false when the program should exit
+    fn handle_key(&mut self, key: Key) -> bool {
+        match &mut self.screen {
+            Screen::MainMenu(menu, items) => match menu.handle_key(key, &self.settings) {
+                MenuEvent::Select(idx) => match items[idx] {
+                    Msg::Continue => match SavedGame::load() {
+                        Some(saved) => self.screen = Screen::Continue(saved),
+                        None => self.screen = Self::main_menu(Msg::Play)
+                    },
+                    Msg::Play => self.screen = Self::mode_select(),
+                    Msg::Versus => self.screen = Screen::Versus(Opponent::Cpu),
+                    Msg::TwoPlayers => self.screen = Screen::Versus(Opponent::Player),
+                    Msg::HighScores => self.screen = Screen::HighScores(0, HighScores::load()),
+                    Msg::Replays => self.screen = Self::replays(),
+                    Msg::Demo => self.screen = Screen::Demo,
+                    Msg::Settings => self.screen = Screen::Settings(SettingsScreen::new(&self.settings)),
+                    Msg::Controls => self.screen = Screen::Controls,
+                    _ => return false
+                },
+                MenuEvent::Quit if menu.selected == items.len() - 1 => return false,
+                MenuEvent::Back | MenuEvent::Quit => menu.selected = items.len() - 1,
+                _ => {}
+            },
+            Screen::ModeSelect(menu) => match menu.handle_key(key, &self.settings) {
+                MenuEvent::Select(idx) if idx < GAME_MODES.len() => self.screen = Screen::Play(GAME_MODES[idx]),
+                MenuEvent::Select(_) | MenuEvent::Back | MenuEvent::Quit => self.screen = Self::main_menu(Msg::Play),
+                _ => {}
+            },
+            Screen::Replays(menu, paths) => match menu.handle_key(key, &self.settings) {
+                MenuEvent::Select(idx) if idx < paths.len() => {
+                    // A replay which can not be read stays in the list
+                    if let Ok(replay) = Replay::load(&paths[idx]) {
+                        self.screen = Screen::Replay(replay);
+                    }
+                }
+                MenuEvent::Select(_) | MenuEvent::Back | MenuEvent::Quit => self.screen = Self::main_menu(Msg::Replays),
+                _ => {}
+            },
+            Screen::Settings(screen) => {
+                if screen.handle_key(key, &mut self.settings) {
+                    self.screen = Self::main_menu(Msg::Settings);
+                }
+            }
+            Screen::HighScores(mode_idx, _) => {
+                // Left and right switch between the game modes
+                match (key, self.settings.action(key)) {
+                    (Key::Left, _) | (_, Some(Action::Left)) => {
+                        *mode_idx = (*mode_idx + GAME_MODES.len() - 1) % GAME_MODES.len();
+                    }
+                    (Key::Right, _) | (_, Some(Action::Right)) => {
+                        *mode_idx = (*mode_idx + 1) % GAME_MODES.len();
+                    }
+                    _ => {
+                        if Self::is_back_key(key, &self.settings) {
+                            self.screen = Self::main_menu(Msg::HighScores);
+                        }
+                    }
+                }
+            }
+            Screen::Controls => {
+                if Self::is_back_key(key, &self.settings) {
+                    self.screen = Self::main_menu(Msg::Controls);
+                }
+            }
+            Screen::Play(_) | Screen::Continue(_) | Screen::Replay(_) | Screen::Demo | Screen::Versus(_) => {}
+        }
+        true
+    }
+
+    fn is_back_key(key: Key, settings: &Settings) -> bool {
+        matches!(key, Key::Enter | Key::Esc | Key::Char(' ')) || settings.action(key) == Some(Action::Quit)
+    }
+
+    fn draw(&mut self) {
+        self.canvas.clear();
+        let center_x = self.canvas.width() / 2;
+        let center_y = self.canvas.height() / 2;
+
+        let logo_top = center_y.saturating_sub(10);
+        for (idx, line) in LOGO.iter().enumerate() {
+            self.canvas.draw_text(line, center_x.saturating_sub(ioscreen::text_width(line) / 2), logo_top + idx);
+        }
+
+        let menu_y = center_y + 2;
+        match &self.screen {
+            Screen::MainMenu(menu, _) | Screen::ModeSelect(menu) | Screen::Replays(menu, _) => menu.draw(&mut self.canvas, center_x, menu_y),
+            Screen::Settings(screen) => screen.draw(&mut self.canvas, center_x, menu_y),
+            Screen::HighScores(mode_idx, high_scores) => {
+                let mode = GAME_MODES[*mode_idx];
+                let mut lines = vec![format!("← {} →", tr_args(Msg::HighScoresOf, &[&mode.title()])), String::new()];
+                lines.extend(high_scores.table_lines(mode, None));
+                lines.push(String::new());
+                lines.push(format!("> {} <", tr(Msg::Back)));
+                menu::draw_panel(&mut self.canvas, &lines, center_x, menu_y);
+            }
+            Screen::Controls => {
+                // Keys of the two players are in columns of their own
+                let split_header = (1..=2).map(|player| ioscreen::pad_left(&player.to_string(), 3)).collect::<String>();
+                let mut lines = vec![tr(Msg::Controls).to_string(), format!("{}{}", " ".repeat(17), split_header)];
+                let mut bindings: Vec<(Action, Key)> = ACTIONS.iter()
+                    .map(|action| (*action, self.settings.key(*action)))
+                    .collect();
+                bindings.push((Action::Drop, Key::Char(' ')));
+                bindings.push((Action::Pause, Key::Esc));
+                for (idx, (action, key)) in bindings.into_iter().enumerate() {
+                    let split_keys: String = (0..2).map(|player| match self.settings.split_key(player, action) {
+                        Some(key) if idx < ACTIONS.len() => ioscreen::pad_left(&key.title(), 3),
+                        _ => String::new()
+                    }).collect();
+                    lines.push(ioscreen::pad_right(&format!("{} {}{}", ioscreen::pad_right(action.title(), 10),
+                                                            ioscreen::pad_left(&key.title(), 6), split_keys), 23));
+                }
+                lines.push(String::new());
+                lines.push(format!("> {} <", tr(Msg::Back)));
+                menu::draw_panel(&mut self.canvas, &lines, center_x, menu_y);
+            }
+            Screen::Play(_) | Screen::Continue(_) | Screen::Replay(_) | Screen::Demo | Screen::Versus(_) => {}
+        }
+        self.canvas.display();
+    }
+}
@@ -0,0 +1,288 @@
+// Tunes the weights of the computer player with the cross entropy method:
+// every generation samples weights around the current mean, plays seeded
+// games with each of them on all cores and moves the mean to the best ones.
+// The same seed always gives the same weights
+use rustris::ai::{self, Weights, WEIGHT_COUNT};
+use rustris::game::{DEFAULT_GLASS_HEIGHT, DEFAULT_GLASS_WIDTH, GLASS_HEIGHT_RANGE, GLASS_WIDTH_RANGE};
+use rustris::piece::PieceSet;
+use rustris::random::Random;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+
+struct Options {
+    generations: u32,
+    population: usize,
+    // Best candidates the next mean is taken from
+    elite: usize,
+    games: u32,
+    // Games are cut off after this many pieces, a good player never loses
+    pieces: u32,
+    seed: u64,
+    piece_set: PieceSet,
+    glass_size: (usize, usize),
+    threads: usize,
+    out: Option<PathBuf>
+}
+
+// Spread of the first generation around the default weights
+const INITIAL_DEVIATION: f64 = 0.5;
+// Added to the spread so it does not collapse too early, less every generation
+const EXTRA_DEVIATION: f64 = 0.1;
+// Games every mean is checked with, the same ones in every generation
+const VALIDATION_GAMES: u32 = 8;
+const VALIDATION_SEED: u64 = 0x5EED;
+
+fn usage() -> ! {
+    eprintln!("usage: rustris-tune [--generations N] [--population N] [--elite N] [--games N] [--pieces N]");
+    eprintln!("                    [--seed HEX] [--set NAME] [--glass WxH] [--threads N] [--out FILE]");
+    process::exit(2);
+}
+
+fn number<T: FromStr>(value: Option<String>) -> T {
+    value.and_then(|value| value.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn default_options() -> Options {
+    Options {
+        generations: 10,
+        population: 32,
+        elite: 8,
+        games: 4,
+        pieces: 500,
+        seed: 1,
+        piece_set: PieceSet::standard(),
+        glass_size: (DEFAULT_GLASS_WIDTH, DEFAULT_GLASS_HEIGHT),
+        threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        out: Weights::path()
+    }
+}
+
+fn parse_options() -> Options {
+    let mut options = default_options();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--generations" => options.generations = number(args.next()),
+            "--population" => options.population = number(args.next()),
+            "--elite" => options.elite = number(args.next()),
+            "--games" => options.games = number(args.next()),
+            "--pieces" => options.pieces = number(args.next()),
+            "--threads" => options.threads = number(args.next()),
+            "--seed" => {
+                options.seed = args.next().and_then(|seed| u64::from_str_radix(&seed, 16).ok()).unwrap_or_else(|| usage());
+            }
+            "--set" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                options.piece_set = PieceSet::find(&name).unwrap_or_else(|| {
+                    eprintln!("rustris-tune: no piece set {}", name);
+                    process::exit(1);
+                });
+            }
+            "--glass" => {
+                let size = args.next().unwrap_or_else(|| usage());
+                let (width, height) = size.split_once('x').unwrap_or_else(|| usage());
+                options.glass_size = (number(Some(width.to_string())), number(Some(height.to_string())));
+            }
+            "--out" => options.out = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ => usage()
+        }
+    }
+    let (width, height) = options.glass_size;
+    let sizes_fit = GLASS_WIDTH_RANGE.contains(&width) && GLASS_HEIGHT_RANGE.contains(&height);
+    if !sizes_fit || options.population == 0 || options.elite == 0 || options.elite > options.population
+        || options.threads == 0 {
+        usage();
+    }
+    options
+}
+
+// Normal distribution by the Box-Muller transform
+fn normal(random: &mut Random) -> f64 {
+    let radius = (-2.0 * (1.0 - random.next_f64()).ln()).sqrt();
+    radius * (2.0 * std::f64::consts::PI * random.next_f64()).cos()
+}
+
+// Only the order of placement scores matters, so weights are kept at
+// length 1 and the search can not drift off in scale
+fn normalized(mut values: [f64; WEIGHT_COUNT]) -> [f64; WEIGHT_COUNT] {
+    let length = values.iter().map(|value| value * value).sum::<f64>().sqrt();
+    if length > 0.0 {
+        for value in &mut values {
+            *value /= length;
+        }
+    }
+    values
+}
+
+// Lines cleared in all the games together
+fn fitness(options: &Options, weights: &Weights, seeds: &[u64]) -> u64 {
+    seeds.iter()
+        .map(|seed| ai::play(&options.piece_set, options.glass_size, *seed, weights, options.pieces).lines as u64)
+        .sum()
+}
+
+// Candidates are shared out between the threads, the results do not depend
+// on how many there are
+fn fitness_all(options: &Options, candidates: &[Weights], seeds: &[u64]) -> Vec<u64> {
+    let chunk = candidates.len().div_ceil(options.threads);
+    thread::scope(|scope| {
+        let handles: Vec<_> = candidates.chunks(chunk).map(|chunk| {
+            scope.spawn(move || chunk.iter().map(|weights| fitness(options, weights, seeds)).collect::<Vec<u64>>())
+        }).collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    })
+}
+
+fn seeds(random: &mut Random, count: u32) -> Vec<u64> {
+    (0..count).map(|_| random.next_u64()).collect()
+}
+
+// Candidates for one generation, spread around the mean
+fn sample(random: &mut Random, mean: &[f64; WEIGHT_COUNT], deviation: &[f64; WEIGHT_COUNT], count: usize) -> Vec<Weights> {
+    (0..count).map(|_| {
+        let mut values = *mean;
+        for (value, spread) in values.iter_mut().zip(deviation) {
+            *value += spread * normal(random);
+        }
+        Weights::from_array(normalized(values))
+    }).collect()
+}
+
+// Candidates with the best scores, best first. Equal scores keep the order
+// of the candidates
+fn select_elite(scores: Vec<u64>, candidates: Vec<Weights>, elite: usize) -> Vec<(u64, Weights)> {
+    let mut ranked: Vec<(u64, Weights)> = scores.into_iter().zip(candidates).collect();
+    ranked.sort_by(|(score, _), (other, _)| other.cmp(score));
+    ranked.truncate(elite);
+    ranked
+}
+
+// Mean and spread of the next generation: the mean and the deviation of the
+// elite, with the extra deviation added to the spread
+fn next_distribution(elite: &[Weights], extra: f64) -> ([f64; WEIGHT_COUNT], [f64; WEIGHT_COUNT]) {
+    let elite: Vec<[f64; WEIGHT_COUNT]> = elite.iter().map(|weights| weights.to_array()).collect();
+    let mut mean = [0.0; WEIGHT_COUNT];
+    let mut deviation = [0.0; WEIGHT_COUNT];
+    for idx in 0..WEIGHT_COUNT {
+        let elite_mean = elite.iter().map(|values| values[idx]).sum::<f64>() / elite.len() as f64;
+        let variance = elite.iter().map(|values| (values[idx] - elite_mean).powi(2)).sum::<f64>() / elite.len() as f64;
+        mean[idx] = elite_mean;
+        deviation[idx] = variance.sqrt() + extra;
+    }
+    (normalized(mean), deviation)
+}
+
+// Searches from the given weights. The best mean of all generations comes
+// back with its lines in the validation games, the start is one of them
+fn tune(options: &Options, start: &Weights) -> (Weights, u64) {
+    let mut random = Random::new(options.seed);
+    let validation_seeds = seeds(&mut Random::new(VALIDATION_SEED), VALIDATION_GAMES);
+    let mut mean = normalized(start.to_array());
+    let mut deviation = [INITIAL_DEVIATION; WEIGHT_COUNT];
+    let mut best = (Weights::from_array(mean), fitness(options, &Weights::from_array(mean), &validation_seeds));
+    println!("start weights: {} lines in {} validation games", best.1, VALIDATION_GAMES);
+
+    for generation in 0..options.generations {
+        let candidates = sample(&mut random, &mean, &deviation, options.population);
+        // Every candidate plays the same games, luck is the same for all
+        let game_seeds = seeds(&mut random, options.games);
+        let scores = fitness_all(options, &candidates, &game_seeds);
+        let elite = select_elite(scores, candidates, options.elite);
+        let elite_weights: Vec<Weights> = elite.iter().map(|(_, weights)| *weights).collect();
+        (mean, deviation) = next_distribution(&elite_weights, EXTRA_DEVIATION / (generation + 1) as f64);
+
+        let mean_weights = Weights::from_array(mean);
+        let validation = fitness(options, &mean_weights, &validation_seeds);
+        println!("generation {}/{}: best {} lines, elite mean {} lines in validation games",
+                 generation + 1, options.generations, elite[0].0, validation);
+        if validation > best.1 {
+            best = (mean_weights, validation);
+        }
+    }
+    best
+}
+
+fn main() {
+    let options = parse_options();
+    let best = tune(&options, &Weights::default());
+
+    let path = match &options.out {
+        Some(path) => path,
+        None => {
+            eprintln!("rustris-tune: no place to write the weights to, use --out");
+            process::exit(1);
+        }
+    };
+    if let Err(error) = best.0.save_to(path) {
+        eprintln!("rustris-tune: can not write {}: {}", path.display(), error);
+        process::exit(1);
+    }
+    println!("{} lines in validation games, weights written to {}", best.1, path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POINT: [f64; WEIGHT_COUNT] = [-0.5, -0.5, -0.5, 0.5, 0.0];
+
+    fn close(values: &[f64; WEIGHT_COUNT], expected: &[f64; WEIGHT_COUNT], within: f64) -> bool {
+        values.iter().zip(expected).all(|(value, expected)| (value - expected).abs() <= within)
+    }
+
+    #[test]
+    fn elites_at_a_point_pull_the_mean_there_and_narrow_the_spread() {
+        let elite = vec![Weights::from_array(POINT); 4];
+        let (mean, deviation) = next_distribution(&elite, 0.01);
+        assert!(close(&mean, &normalized(POINT), 1e-12));
+        assert!(close(&deviation, &[0.01; WEIGHT_COUNT], 1e-12));
+    }
+
+    #[test]
+    fn elites_around_a_point_give_their_spread() {
+        let elite: Vec<Weights> = [-0.1, 0.1].iter().map(|offset| {
+            let mut values = POINT;
+            values[4] = *offset;
+            Weights::from_array(values)
+        }).collect();
+        let (mean, deviation) = next_distribution(&elite, 0.0);
+        assert!(close(&mean, &normalized(POINT), 1e-12));
+        assert!(close(&deviation, &[0.0, 0.0, 0.0, 0.0, 0.1], 1e-12));
+    }
+
+    #[test]
+    fn elites_are_the_best_scores_in_order() {
+        let candidates: Vec<Weights> = (0..4).map(|idx| Weights::from_array([idx as f64; WEIGHT_COUNT])).collect();
+        let elite = select_elite(vec![5, 9, 1, 9], candidates, 3);
+        let picked: Vec<(u64, f64)> = elite.iter().map(|(score, weights)| (*score, weights.holes)).collect();
+        assert_eq!(picked, [(9, 1.0), (9, 3.0), (5, 0.0)]);
+    }
+
+    #[test]
+    fn samples_are_normalized_and_follow_the_seed() {
+        let mean = normalized(POINT);
+        let samples = sample(&mut Random::new(3), &mean, &[0.2; WEIGHT_COUNT], 16);
+        assert!(samples == sample(&mut Random::new(3), &mean, &[0.2; WEIGHT_COUNT], 16));
+        for weights in samples {
+            let length = weights.to_array().iter().map(|value| value * value).sum::<f64>().sqrt();
+            assert!((length - 1.0).abs() < 1e-9);
+        }
+        // No spread, no change
+        let still = sample(&mut Random::new(3), &mean, &[0.0; WEIGHT_COUNT], 2);
+        assert!(still.iter().all(|weights| close(&weights.to_array(), &mean, 1e-12)));
+    }
+
+    #[test]
+    fn a_seeded_search_improves_on_poor_weights() {
+        let options = Options {generations: 1, population: 8, elite: 2, games: 1, pieces: 60, threads: 4, ..default_options()};
+        // Lines alone leave placements which clear none to chance
+        let poor = Weights {aggregate_height: 0.0, holes: 0.0, bumpiness: 0.0, lines: 1.0, wells: 0.0};
+        let start = fitness(&options, &poor, &seeds(&mut Random::new(VALIDATION_SEED), VALIDATION_GAMES));
+        let (weights, lines) = tune(&options, &poor);
+        assert!(lines > start);
+        assert!(weights != Weights::from_array(normalized(poor.to_array())));
+    }
+}
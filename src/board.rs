@@ -0,0 +1,135 @@
+// Frozen cells of the glass as one bit mask per row. Collision, full row
+// checks and clearing work on whole rows instead of single cells, which
+// matters to anything trying many placements
+
+// Widest glass a row mask can hold
+pub const MAX_BOARD_WIDTH: usize = 64;
+
+// Boards compare and hash by their cells, so searches can tell positions
+// they have seen already
+#[derive(PartialEq, Eq, Hash)]
+pub struct Board {
+    width: usize,
+    // Mask of a row with every cell filled
+    full_row: u64,
+    // Rows from the top, bit x is the cell in column x
+    rows: Vec<u64>
+}
+
+// Copying into an existing board reuses its rows, searches copy a lot
+impl Clone for Board {
+    fn clone(&self) -> Self {
+        Board {width: self.width, full_row: self.full_row, rows: self.rows.clone()}
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.width = source.width;
+        self.full_row = source.full_row;
+        self.rows.clone_from(&source.rows);
+    }
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && width <= MAX_BOARD_WIDTH, "board width {} is out of range", width);
+        let full_row = if width == MAX_BOARD_WIDTH {u64::MAX} else {(1 << width) - 1};
+        Board {width, full_row, rows: vec![0; height]}
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn row(&self, y: usize) -> u64 {
+        self.rows[y]
+    }
+
+    pub fn is_filled(&self, x: usize, y: usize) -> bool {
+        self.rows[y] >> x & 1 != 0
+    }
+
+    pub fn fill(&mut self, x: usize, y: usize) {
+        self.rows[y] |= 1 << x;
+    }
+
+    // True when every cell is inside the board and free
+    pub fn fits(&self, cells: &[(usize, usize)]) -> bool {
+        cells.iter().all(|(x, y)| *x < self.width && *y < self.rows.len() && !self.is_filled(*x, *y))
+    }
+
+    // Same for a piece given as row masks, bit 0 of a mask is the leftmost
+    // column of the piece, and its top left corner at x, y
+    pub fn fits_rows(&self, piece_rows: &[u64], x: usize, y: usize) -> bool {
+        if x >= self.width || y + piece_rows.len() > self.rows.len() {
+            return false;
+        }
+        piece_rows.iter().zip(&self.rows[y..]).all(|(piece_row, row)| {
+            // Bits shifted past the right wall are lost, so they are checked
+            // against the free columns first
+            *piece_row <= self.full_row >> x && piece_row << x & row == 0
+        })
+    }
+
+    pub fn is_row_full(&self, y: usize) -> bool {
+        self.rows[y] == self.full_row
+    }
+
+    // Moves every row up by count, rows falling off the top are lost. The
+    // new rows at the bottom are full except for the hole column
+    pub fn push_rows_up(&mut self, count: usize, hole: usize) {
+        let count = count.min(self.rows.len());
+        self.rows.rotate_left(count);
+        let garbage = self.full_row & !(1 << hole);
+        let height = self.rows.len();
+        self.rows[height - count..].fill(garbage);
+    }
+
+    // Returns the number of rows cleared, the rows above fall down
+    pub fn clear_full_rows(&mut self) -> u32 {
+        let mut kept_top = self.rows.len();
+        for y in (0..self.rows.len()).rev() {
+            if self.rows[y] != self.full_row {
+                kept_top -= 1;
+                self.rows[kept_top] = self.rows[y];
+            }
+        }
+        self.rows[..kept_top].fill(0);
+        kept_top as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceSet;
+    use crate::random::Random;
+
+    #[test]
+    fn row_masks_fit_where_cells_fit() {
+        let mut board = Board::new(10, 20);
+        let mut random = Random::new(7);
+        for y in 8..20 {
+            for x in 0..10 {
+                if random.below(3) == 0 {
+                    board.fill(x, y);
+                }
+            }
+        }
+        for name in ["tetromino", "pentomino", "extras", "easy"] {
+            for piece in PieceSet::builtin(name).unwrap().pieces {
+                for rotation in 0..piece.rotation_count() {
+                    for y in 0..board.height() + 2 {
+                        for x in 0..board.width() + 2 {
+                            assert_eq!(board.fits_rows(piece.rows(rotation), x, y),
+                                       board.fits(&piece.cells(rotation, x, y)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+// One glass and the shape falling in it: the rules of the game without the
+// clock, the menus or the keyboard. A game runs one field, versus games run
+// one per player
+use crate::board::Board;
+use crate::gravity::{GravityCurve, GRAVITY_UNIT};
+use crate::ioscreen::{Canvas, Color};
+use crate::piece::{Piece, PieceSet};
+use crate::random::Random;
+use crate::score::Score;
+use crate::settings::Action;
+
+// Logic frames a landed shape waits before it freezes
+pub const LOCK_DELAY_FRAMES: u32 = 30;
+// Garbage rows are grey
+pub const GARBAGE_COLOR: Color = Color::Grey;
+
+// Falling shape, the piece is an index into the piece set of the field
+#[derive(Clone, Copy)]
+pub struct ShapeState {
+    pub piece: usize,
+    pub position: usize,
+    pub x: usize,
+    pub y: usize
+}
+
+impl ShapeState {
+    fn new_position(pieces: &PieceSet, piece: usize, position: usize, glass_width: usize) -> Self {
+        let position = if position < pieces.pieces[piece].rotation_count() {position} else {0};
+        ShapeState {piece, position, x: pieces.pieces[piece].spawn_x(position, glass_width), y: 0}
+    }
+
+    fn new_random(pieces: &PieceSet, random: &mut Random, glass_width: usize) -> Self {
+        let piece = pieces.random_piece(random);
+        let position = random.below(pieces.pieces[piece].rotation_count());
+        Self::new_position(pieces, piece, position, glass_width)
+    }
+
+    pub fn cells(&self, pieces: &PieceSet) -> Vec<(usize, usize)> {
+        pieces.pieces[self.piece].cells(self.position, self.x, self.y)
+    }
+}
+
+// What a logic frame did to the field
+#[derive(Clone, Copy, Default)]
+pub struct FrameEvents {
+    // A shape froze, cleared this many rows and the next one came
+    pub locked: bool,
+    pub rows_cleared: u32,
+    // The next shape had no room in the glass
+    pub topped_out: bool
+}
+
+pub struct Field {
+    pub pieces: PieceSet,
+    pub shape: ShapeState,
+    pub random: Random,
+    // Frozen cells only, the falling shape is added when drawing
+    pub glass: Board,
+    // Colour of every frozen cell, rows kept in step with the glass
+    pub glass_colors: Vec<Vec<Option<Color>>>,
+    // Rows which came in as garbage, kept in step with the glass too
+    pub garbage: Vec<bool>,
+    pub gravity_curve: GravityCurve,
+    pub score: Score,
+    pub fall_progress: u32,
+    pub lock_frames: u32,
+    // Replays keep the delay they were recorded with
+    pub lock_delay: u32
+}
+
+impl Field {
+    pub fn new(pieces: PieceSet, seed: u64, glass_size: (usize, usize), gravity_curve: GravityCurve) -> Self {
+        let (width, height) = glass_size;
+        let mut random = Random::new(seed);
+        Field {
+            shape: ShapeState::new_random(&pieces, &mut random, width),
+            pieces,
+            random,
+            glass: Board::new(width, height),
+            glass_colors: vec![vec![None; width]; height],
+            garbage: vec![false; height],
+            score: Score::new(&gravity_curve),
+            gravity_curve,
+            fall_progress: 0,
+            lock_frames: 0,
+            lock_delay: LOCK_DELAY_FRAMES
+        }
+    }
+
+    pub fn piece(&self) -> &Piece {
+        &self.pieces.pieces[self.shape.piece]
+    }
+
+    // Rotation and position of the falling shape
+    pub fn position(&self) -> (usize, usize, usize) {
+        (self.shape.position, self.shape.x, self.shape.y)
+    }
+
+    pub fn shape_fits(&self, shape: &ShapeState) -> bool {
+        self.glass.fits_rows(self.pieces.pieces[shape.piece].rows(shape.position), shape.x, shape.y)
+    }
+
+    fn freeze_shape(&mut self) {
+        let color = self.piece().color;
+        for (x, y) in self.shape.cells(&self.pieces) {
+            self.glass.fill(x, y);
+            self.glass_colors[y][x] = color;
+        }
+    }
+
+    fn move_shape(&mut self, action: Action) {
+        let old_x = self.shape.x;
+        let old_position = self.shape.position;
+        match action {
+            Action::Left if self.shape.x > 0 => {
+                self.shape.x -= 1;
+            }
+            Action::Rotate => {
+                self.shape.position = (old_position + 1) % self.piece().rotation_count();
+            }
+            Action::Right => {
+                self.shape.x += 1;
+            }
+            _ => {}
+        }
+
+        if !self.shape_fits(&self.shape) {
+            self.shape.x = old_x;
+            self.shape.position = old_position;
+        }
+    }
+
+    // Freezes the shape, clears full rows and brings the next shape
+    fn lock_shape(&mut self) -> FrameEvents {
+        self.freeze_shape();
+        for y in 0..self.glass.height() {
+            if self.glass.is_row_full(y) {
+                self.glass_colors.remove(y);
+                self.glass_colors.insert(0, vec![None; self.glass.width()]);
+                self.garbage.remove(y);
+                self.garbage.insert(0, false);
+            }
+        }
+        let rows_cleared = self.glass.clear_full_rows();
+        self.score.rows_cleared(rows_cleared);
+        self.shape = ShapeState::new_random(&self.pieces, &mut self.random, self.glass.width());
+        self.score.shape_spawned();
+        self.fall_progress = 0;
+        self.lock_frames = 0;
+        FrameEvents {locked: true, rows_cleared, topped_out: !self.shape_fits(&self.shape)}
+    }
+
+    fn drop_shape(&mut self) -> FrameEvents {
+        loop {
+            self.shape.y += 1;
+            if !self.shape_fits(&self.shape) {
+                self.shape.y -= 1;
+                return self.lock_shape();
+            }
+        }
+    }
+
+    // One logic frame with the actions of the player
+    pub fn update(&mut self, actions: &[Action]) -> FrameEvents {
+        for action in actions {
+            match action {
+                Action::SpeedUp => self.score.speed_up(),
+                Action::Drop => return self.drop_shape(),
+                _ => self.move_shape(*action)
+            }
+        }
+
+        self.fall_progress += self.gravity_curve.gravity(self.score.speed_level());
+        let mut is_on_floor = false;
+        while self.fall_progress >= GRAVITY_UNIT {
+            self.fall_progress -= GRAVITY_UNIT;
+            self.shape.y += 1;
+            if !self.shape_fits(&self.shape) {
+                self.shape.y -= 1;
+                self.fall_progress = 0;
+                is_on_floor = true;
+            }
+        }
+
+        // A landed shape shifted off a ledge falls again and gets the whole
+        // delay once it lands anew
+        let below = ShapeState {y: self.shape.y + 1, ..self.shape};
+        if self.lock_frames > 0 && self.shape_fits(&below) {
+            self.lock_frames = 0;
+        }
+
+        if is_on_floor || self.lock_frames > 0 {
+            // Once landed, the shape can still be shifted for a short while
+            self.lock_frames += 1;
+            if self.lock_frames >= self.lock_delay {
+                return self.lock_shape();
+            }
+        }
+
+        FrameEvents::default()
+    }
+
+    // Pushes the glass up by rows with an empty cell in the hole column.
+    // Returns true when frozen cells were pushed out of the top or the
+    // falling shape has no room left
+    pub fn add_garbage(&mut self, rows: usize, hole: usize) -> bool {
+        let width = self.glass.width();
+        let rows = rows.min(self.glass.height());
+        let pushed_out = (0..rows).any(|y| (0..width).any(|x| self.glass.is_filled(x, y)));
+        let garbage_row: Vec<Option<Color>> = (0..width).map(|x| if x == hole {None} else {Some(GARBAGE_COLOR)}).collect();
+        self.glass.push_rows_up(rows, hole);
+        self.glass_colors.drain(..rows);
+        self.glass_colors.extend((0..rows).map(|_| garbage_row.clone()));
+        self.garbage.drain(..rows);
+        self.garbage.extend((0..rows).map(|_| true));
+
+        // The falling shape goes up with the glass as far as it can
+        for _ in 0..rows {
+            if self.shape_fits(&self.shape) || self.shape.y == 0 {
+                break;
+            }
+            self.shape.y -= 1;
+        }
+        pushed_out || !self.shape_fits(&self.shape)
+    }
+
+    // Rows with garbage in them, garbage leaves the glass only with its row
+    // cleared
+    pub fn garbage_rows(&self) -> u32 {
+        self.garbage.iter().filter(|garbage| **garbage).count() as u32
+    }
+
+    // Glass as text, a row per line: '.' for an empty cell, '#' for a cell
+    // without a colour and the colour code for the rest. Saved games and
+    // snapshots of network games keep glasses this way
+    pub fn rows(&self, with_shape: bool) -> Vec<String> {
+        let shape = if with_shape {self.shape.cells(&self.pieces)} else {Vec::new()};
+        let shape_color = self.piece().color;
+        (0..self.glass.height()).map(|y| (0..self.glass.width()).map(|x| {
+            let color = if shape.contains(&(x, y)) {
+                shape_color
+            } else if self.glass.is_filled(x, y) {
+                self.glass_colors[y][x]
+            } else {
+                return '.';
+            };
+            color.map_or('#', |color| color.code())
+        }).collect()).collect()
+    }
+
+    // The falling shape is left out once the game is over, the last one
+    // did not fit anyway
+    pub fn draw(&self, canvas: &mut Canvas, glass_left: usize, glass_top: usize, with_shape: bool) {
+        draw_rows(canvas, &self.rows(with_shape), glass_left, glass_top);
+    }
+
+    pub fn draw_border(&self, canvas: &mut Canvas, glass_left: usize, glass_top: usize) {
+        draw_border(canvas, glass_left, glass_top, self.glass.width(), self.glass.height());
+    }
+}
+
+// Glass contents kept as text by Field::rows
+pub fn draw_rows(canvas: &mut Canvas, rows: &[String], glass_left: usize, glass_top: usize) {
+    for (y, row) in rows.iter().enumerate() {
+        for (x, cell) in row.chars().enumerate() {
+            let (char1, char2, color) = match cell {
+                '.' => (' ', '.', None),
+                '#' => ('█', '█', None),
+                code => ('█', '█', Color::from_code(code))
+            };
+            canvas.set_colored_char(glass_left + x*2, glass_top + y, char1, color);
+            canvas.set_colored_char(glass_left + x*2 + 1, glass_top + y, char2, color);
+        }
+    }
+}
+
+// Walls and the bottom around an empty glass
+pub fn draw_border(canvas: &mut Canvas, glass_left: usize, glass_top: usize, width: usize, height: usize) {
+    let glass_right = glass_left + width*2;
+    for line_idx in glass_top..glass_top + height {
+        canvas.set_char(glass_left - 2, line_idx, '<');
+        canvas.set_char(glass_left - 1, line_idx, '!');
+        for dot_x in glass_left..glass_right {
+            if (dot_x - glass_left).is_multiple_of(2) {
+                canvas.set_char(dot_x, line_idx, ' ');
+            } else {
+                canvas.set_char(dot_x, line_idx, '.');
+            }
+        }
+        canvas.set_char(glass_right, line_idx, '!');
+        canvas.set_char(glass_right + 1, line_idx, '>');
+    }
+    let bottom = glass_top + height;
+    canvas.set_char(glass_left - 2, bottom, '<');
+    canvas.set_char(glass_left - 1, bottom, '!');
+    for dot_x in glass_left..glass_right {
+        canvas.set_char(dot_x, bottom, '=');
+        if (dot_x - glass_left).is_multiple_of(2) {
+            canvas.set_char(dot_x, bottom + 1, '\\');
+        } else {
+            canvas.set_char(dot_x, bottom + 1, '/');
+        }
+    }
+    canvas.set_char(glass_right, bottom, '!');
+    canvas.set_char(glass_right + 1, bottom, '>');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shape_shifted_off_a_ledge_does_not_lock_in_the_air() {
+        let mut field = Field::new(PieceSet::standard(), 1, (10, 20), GravityCurve::Guideline);
+        // O resting on a ledge two cells wide in the bottom row
+        field.glass.fill(2, 19);
+        field.glass.fill(3, 19);
+        field.shape = ShapeState {piece: field.pieces.piece_index("O").unwrap(), position: 0, x: 2, y: 17};
+        while field.lock_frames == 0 {
+            field.update(&[]);
+        }
+
+        field.update(&[Action::Right, Action::Right]);
+        assert_eq!(field.lock_frames, 0);
+        for _ in 0..LOCK_DELAY_FRAMES * 2 {
+            assert!(!field.update(&[]).locked);
+        }
+        assert!(field.shape.y > 17);
+    }
+
+    #[test]
+    fn grey_pieces_are_not_garbage() {
+        let mut pieces = PieceSet::standard();
+        let o = pieces.piece_index("O").unwrap();
+        pieces.pieces[o].color = Some(GARBAGE_COLOR);
+        let mut field = Field::new(pieces, 1, (4, 10), GravityCurve::Guideline);
+        field.add_garbage(2, 0);
+        assert_eq!(field.garbage_rows(), 2);
+
+        // Grey Os on the garbage, then the bottom row cleared by filling
+        // its hole
+        field.shape = ShapeState {piece: o, position: 0, x: 1, y: 0};
+        field.update(&[Action::Drop]);
+        assert_eq!(field.garbage_rows(), 2);
+        field.glass.fill(0, 9);
+        field.shape = ShapeState {piece: o, position: 0, x: 2, y: 0};
+        assert_eq!(field.update(&[Action::Drop]).rows_cleared, 1);
+        assert_eq!(field.garbage_rows(), 1);
+        assert!(field.garbage[9]);
+    }
+}
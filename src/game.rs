@@ -1,548 +1,765 @@
-use crate::ioscreen;
-use std::thread;
-use std::time::Duration;
+use crate::ai::{Bot, Weights};
+use crate::i18n::{tr, tr_args, Msg};
+use crate::ioscreen::{self, pad_left, pad_right, text_width, Color};
+use crate::field::{Field, ShapeState, GARBAGE_COLOR};
+use crate::garbage::Cheese;
+use crate::layout::Layout;
+use crate::mode::{GameMode, Goal, Ranking};
+use crate::piece::{PieceSet, DEFAULT_PIECE_SET};
+use crate::ioscreen::Key;
+use crate::menu::{self, Menu, MenuEvent};
+use crate::random::{self, Random};
+use crate::replay::Replay;
+use crate::savegame::SavedGame;
+use crate::highscores::{self, Entry, HighScores};
+use crate::storage;
+use crate::settings::{self, Action, Settings, SettingsScreen, ACTIONS};
+use crate::spectate::{Board, Broadcast, ViewState};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+// Game logic runs at a fixed rate regardless of how often the screen is redrawn
+pub const LOGIC_RATE_HZ: u64 = 60;
+// Logic frames to catch up at most after a stall, the rest is dropped
+const MAX_CATCH_UP_FRAMES: u32 = 10;
+// Logic frames per screen frame a replay can be played at
+const PLAYBACK_SPEEDS: [u32; 5] = [1, 2, 4, 8, 16];
+// Logic frames the demo player waits between its moves
+const DEMO_STEP_FRAMES: u32 = 6;
+// Canvas size when the terminal size is unknown
+pub const GAME_CANVAS_WIDTH: usize = 80;
+pub const GAME_CANVAS_HEIGHT: usize = 25;
+// Glass size in cells, the settings may change it within the ranges
+pub const DEFAULT_GLASS_WIDTH: usize = 10;
+pub const DEFAULT_GLASS_HEIGHT: usize = 20;
+pub const GLASS_WIDTH_RANGE: RangeInclusive<usize> = 4..=40;
+pub const GLASS_HEIGHT_RANGE: RangeInclusive<usize> = 10..=60;
+
+// Field with the rules the replay records
+fn replay_field(replay: &Replay, pieces: PieceSet) -> Field {
+    let glass_size = (replay.glass_width, replay.glass_height);
+    let mut field = Field::new(pieces, replay.seed, glass_size, replay.gravity_curve);
+    field.lock_delay = replay.lock_delay;
+    field
+}
 
-// Use rand crate
-use rand;
+// Modes with pieces of their own ignore the set chosen in the settings
+fn mode_pieces(mode: GameMode, settings: &Settings) -> PieceSet {
+    match mode.piece_set() {
+        Some(name) => PieceSet::find(name).unwrap_or_else(PieceSet::standard),
+        None => settings.piece_set()
+    }
+}
 
-const REFRESH_RATE_MSEC: u64 = 20;
-const MOVE_RATE_MSEC: u64 = 500;
-const SPEED_INCREMENT_STEP: u64 = 100;
-const GAME_CANVAS_WIDTH: usize = 80;
-const GAME_CANVAS_HEIGHT: usize = 25;
-const GLASS_WIDTH: usize = 10;
-const GLASS_HEIGHT: usize = 20;
+const PAUSE_ITEMS: [Msg; 4] = [Msg::Resume, Msg::Restart, Msg::Settings, Msg::Quit];
+const PAUSE_RESUME: usize = 0;
+const PAUSE_RESTART: usize = 1;
+const PAUSE_SETTINGS: usize = 2;
+const PAUSE_QUIT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    // The next shape had no room in the glass
+    ToppedOut,
+    GoalReached,
+    TimeUp
+}
 
+// State of a replay being played back
+struct Playback {
+    // Next input of the replay to apply
+    cursor: usize,
+    speed_idx: usize,
+    paused: bool,
+    // Frames to play while paused, one per step key press
+    steps: u32
+}
 
+enum GameState {
+    Playing,
+    Paused(Menu),
+    Settings(SettingsScreen),
+    // New record, the player types a name for the high score table
+    EnterName(String),
+    // Lines of the high score table if the game made it there
+    GameOver(Option<Vec<String>>)
+}
 
-enum Position {
-    P1x4([[bool; 1]; 4]),
-    P4x2([[bool; 4]; 2]),
-    P2x3([[bool; 2]; 3]),
-    P3x2([[bool; 3]; 2]),
-    P2x2([[bool; 2]; 2]),
+pub struct Game {
+    state: GameState,
+    mode: GameMode,
+    settings: Settings,
+    // Moves of the shape waiting for the next logic frame
+    pending_actions: Vec<Action>,
+    field: Field,
+    seed: u64,
+    // Logic frames played, pauses are not counted
+    frames: u64,
+    screen_canvas: ioscreen::Canvas,
+    layout: Layout,
+    // How the last game ended
+    outcome: Outcome,
+    // Inputs of the game being played, or the replay being played back
+    replay: Replay,
+    playback: Option<Playback>,
+    // Computer player of the demo, any key ends it
+    bot: Option<Bot>,
+    // Spectators watching the glass
+    broadcast: Option<Broadcast>,
+    // Garbage of the dig modes
+    cheese: Option<Cheese>
 }
 
-impl Position {
-    fn position_size(&self) -> (usize, usize) {
-        match self {
-            Self::P1x4(_) => (1, 4),
-            Self::P2x2(_) => (2, 2),
-            Self::P2x3(_) => (2, 3),
-            Self::P3x2(_) => (2, 3),
-            Self::P3x2(_) => (3, 2),
-            Self::P4x2(_) => (4, 2)
-        }
+impl Game {
+    pub fn new(mode: GameMode, settings: Settings)->Self {
+        let glass_size = mode.glass_size((settings.glass_width, settings.glass_height));
+        let pieces = mode_pieces(mode, &settings);
+        let replay = Replay::new(mode, random::new_seed(), glass_size, &pieces);
+        let mut game = Self::with_replay(replay, pieces, settings);
+        game.fill_cheese();
+        game
     }
 
-    fn relative_coordinates_list(&self) -> Vec<(usize, usize)> {
-        let (rows, cols) = self.position_size();
-        let mut coordinates = Vec::new();
-        match self {
-            Self::P1x4(matrix) => {
-                for row in 0..4 {
-                    if matrix[row][0] {
-                        coordinates.push((0, row));
-                    }
-                }
-            }
-            Self::P2x2(matrix) => {
-                for row in 0..2 {
-                    for col in 0..2 {
-                        if matrix[row][col] {
-                            coordinates.push((col, row));
-                        }
-                    }
-                }
-            }
-            Self::P2x3(matrix) => {
-                for row in 0..3 {
-                    for col in 0..2 {
-                        if matrix[row][col] {
-                            coordinates.push((col, row));
-                        }
-                    }
-                }
-            }
-            Self::P3x2(matrix) => {
-                for row in 0..2 {
-                    for col in 0..3 {
-                        if matrix[row][col] {
-                            coordinates.push((col, row));
-                        }
-                    }
-                }
+    // Plays the recorded game back instead of taking the player's keys
+    pub fn from_replay(replay: Replay, settings: Settings) -> Self {
+        let pieces = replay.pieces().unwrap_or_else(PieceSet::standard);
+        let mut game = Self::with_replay(replay, pieces, settings);
+        game.fill_cheese();
+        game.playback = Some(Playback {cursor: 0, speed_idx: 0, paused: false, steps: 0});
+        game
+    }
+
+    // The computer plays, for the title screen
+    pub fn demo(mode: GameMode, settings: Settings) -> Self {
+        let mut game = Self::new(mode, settings);
+        game.bot = Some(Bot::new(Weights::load(), DEMO_STEP_FRAMES));
+        game
+    }
+
+    // Continues a saved game, None if the save does not fit this glass or
+    // its pieces are gone
+    pub fn from_saved(saved: SavedGame, settings: Settings) -> Option<Self> {
+        let glass_width = saved.glass.first()?.chars().count();
+        let glass_height = saved.glass.len();
+        if !GLASS_WIDTH_RANGE.contains(&glass_width) || !GLASS_HEIGHT_RANGE.contains(&glass_height) {
+            return None;
+        }
+        // Pieces changed since the save make another game of it
+        let pieces = PieceSet::find(&saved.piece_set)
+            .filter(|pieces| saved.piece_set_fingerprint.is_none_or(|fingerprint| fingerprint == pieces.fingerprint()))?;
+        let replay = Replay::new(saved.mode, saved.seed, (glass_width, glass_height), &pieces);
+        let mut game = Self::with_replay(replay, pieces, settings);
+        for (y, saved_row) in saved.glass.iter().enumerate() {
+            if saved_row.chars().count() != glass_width {
+                return None;
             }
-            Self::P4x2(matrix) => {
-                for row in 0..2 {
-                    for col in 0..4 {
-                        if matrix[row][col] {
-                            coordinates.push((col, row));
-                        }
+            for (x, cell) in saved_row.chars().enumerate() {
+                match cell {
+                    '.' => {}
+                    '#' => game.field.glass.fill(x, y),
+                    code => {
+                        game.field.glass.fill(x, y);
+                        game.field.glass_colors[y][x] = Some(Color::from_code(code)?);
                     }
                 }
             }
+            // Older saves tell garbage by its colour only
+            game.field.garbage[y] = match &saved.garbage_rows {
+                Some(rows) => rows.contains(&y),
+                None => saved_row.contains(GARBAGE_COLOR.code())
+            };
         }
-        coordinates
-    }
-}
 
-enum PositionOptions {
-    P1(Position),
-    P2(Position, Position),
-    P4(Position, Position, Position, Position),
-}
+        let (piece_name, position, x, y) = saved.shape;
+        let piece = game.field.pieces.piece_index(&piece_name)?;
+        if position >= game.field.pieces.pieces[piece].rotation_count() {
+            return None;
+        }
+        game.field.shape = ShapeState {piece, position, x, y};
+        if !game.field.shape_fits(&game.field.shape) {
+            return None;
+        }
 
-impl PositionOptions {
-    fn position_by_index(&self, idx: usize)->Option<&Position> {
-        match self {
-            Self::P1(p1) => match idx {
-                0 => Some(p1),
-                _ => None
-            },
-            Self::P2(p1, p2) => match idx {
-                0 => Some(p1),
-                1 => Some(p2),
-                _ => None
-            },
-            Self::P4(p1, p2, p3, p4) => match idx {
-                0 => Some(p1),
-                1 => Some(p2),
-                2 => Some(p3),
-                3 => Some(p4),
-                _ => None
-            },
+        game.field.random = Random::new(saved.random_state);
+        game.frames = saved.frames;
+        game.field.score.lines = saved.lines;
+        game.field.score.level = saved.level;
+        game.field.score.speed_bonus = saved.speed_bonus;
+        game.field.score.points = saved.points;
+        game.field.score.pieces = saved.pieces;
+        if saved.mode.cheese_rows(glass_height) > 0 {
+            game.cheese = Some(Cheese::resume(saved.seed, &game.field, saved.garbage_cleared));
         }
+        game.field.fall_progress = saved.fall_progress;
+        game.field.lock_frames = saved.lock_frames;
+        game.replay.inputs = saved.inputs;
+        // The player gets a moment to get ready
+        game.pause(PAUSE_RESUME);
+        Some(game)
     }
-}
-
-enum Shape {
-    I(PositionOptions),
-    J(PositionOptions),
-    L(PositionOptions),
-    O(PositionOptions),
-    S(PositionOptions),
-    T(PositionOptions),
-    Z(PositionOptions)
-}
 
-impl Shape {
-    fn get_position_options(&self) -> &PositionOptions {
-        match &self {
-            Self::I(v) | Self::J(v) | Self::L(v) | Self::O(v) | Self::T(v) | Self::Z(v) | Self::S(v) => v
+    fn to_saved(&self) -> SavedGame {
+        let shape = &self.field.shape;
+        SavedGame {
+            mode: self.mode,
+            seed: self.seed,
+            random_state: self.field.random.state(),
+            frames: self.frames,
+            lines: self.field.score.lines,
+            level: self.field.score.level,
+            speed_bonus: self.field.score.speed_bonus,
+            points: self.field.score.points,
+            pieces: self.field.score.pieces,
+            garbage_cleared: self.cheese.as_ref().map_or(0, |cheese| cheese.cleared),
+            fall_progress: self.field.fall_progress,
+            lock_frames: self.field.lock_frames,
+            piece_set: self.field.pieces.name.clone(),
+            piece_set_fingerprint: Some(self.field.pieces.fingerprint()),
+            shape: (self.field.pieces.pieces[shape.piece].name.clone(), shape.position, shape.x, shape.y),
+            glass: self.field.rows(false),
+            garbage_rows: Some((0..self.field.garbage.len()).filter(|y| self.field.garbage[*y]).collect()),
+            inputs: self.replay.inputs.clone()
         }
     }
 
-    fn position_by_index(&self, index: usize)->Option<&Position> {
-        self.get_position_options().position_by_index(index)
+    // Called when the game is left, a game still in progress is saved to
+    // be continued later
+    pub fn save_unfinished(&self) {
+        let unfinished = matches!(self.state, GameState::Playing | GameState::Paused(_) | GameState::Settings(_));
+        if unfinished && self.playback.is_none() && self.bot.is_none() && self.frames > 0 {
+            self.to_saved().save();
+        }
     }
 
-    fn get_shape_coordinates(&self, position: usize, x: usize, y: usize)->Vec<(usize, usize)> {
-        let mut coordinates = Vec::new();
-        for (rel_x, rel_y) in self.position_by_index(position).unwrap().relative_coordinates_list() {
-            coordinates.push((x as usize + rel_x, y as usize + rel_y));
+    // New games start with an empty replay of their own
+    fn with_replay(replay: Replay, pieces: PieceSet, settings: Settings) -> Self {
+        let (width, height) = ioscreen::screen_size().unwrap_or((GAME_CANVAS_WIDTH, GAME_CANVAS_HEIGHT));
+        Game {
+            state: GameState::Playing,
+            mode: replay.mode,
+            settings,
+            pending_actions: Vec::new(),
+            field: replay_field(&replay, pieces),
+            seed: replay.seed,
+            frames: 0,
+            screen_canvas: ioscreen::Canvas::new(width, height),
+            layout: Layout::new(width, height, replay.glass_width, replay.glass_height),
+            replay,
+            outcome: Outcome::ToppedOut,
+            playback: None,
+            bot: None,
+            broadcast: None,
+            cheese: None
         }
-        coordinates
     }
 
-    fn get_position_count(&self)->usize {
-        match self.get_position_options() {
-            PositionOptions::P1(_) => 1,
-            PositionOptions::P2(_, _) => 2,
-            PositionOptions::P4(_, _, _, _) => 4
+    // The dig modes start with garbage in the glass, it always fits
+    fn fill_cheese(&mut self) {
+        let rows = self.mode.cheese_rows(self.field.glass.height());
+        self.cheese = (rows > 0).then(|| Cheese::new(self.seed, &mut self.field, rows));
+    }
+
+    fn handle_resize(&mut self) {
+        if let Some((width, height)) = ioscreen::take_resize() {
+            self.screen_canvas = ioscreen::Canvas::new(width, height);
+            self.layout = Layout::new(width, height, self.field.glass.width(), self.field.glass.height());
+            ioscreen::clear_screen();
         }
     }
-}
 
-const ISHAPE: Shape = Shape::I(PositionOptions::P2(
-        Position::P1x4([[true], [true], [true], [true]]),
-        Position::P4x2([[false, false, false, false], [true, true, true, true]])
-    )
-);
-
-const JSHAPE: Shape = Shape::J(PositionOptions::P4(
-        Position::P2x3([[false, true], [false, true], [true, true]]),
-        Position::P3x2([[true, false, false], [true, true, true]]),
-        Position::P2x3([[true, true], [true, false], [true, false]]),
-        Position::P3x2([[true, true, true], [false, false, true]])
-    )
-);
-
-const LSHAPE: Shape = Shape::L (PositionOptions::P4(
-        Position::P2x3([[true, false], [true, false], [true, true]]),
-        Position::P3x2([[true, true, true], [true, false, false]]),
-        Position::P2x3([[true, true], [false, true], [false, true]]),
-        Position::P3x2([[false, false, true], [true, true, true]])
-    )
-);
-
-const OSHAPE: Shape = Shape::O(PositionOptions::P1(Position::P2x2([[true, true], [true, true]])));
-
-const SSHAPE: Shape = Shape::S(PositionOptions::P2(
-        Position::P3x2([[false, true, true], [true, true, false]]),
-        Position::P2x3([[true, false],[true, true], [false, true]])
-    )
-);
-
-const TSHAPE: Shape = Shape::T(PositionOptions::P4(
-        Position::P3x2([[true, true, true], [false, true, false]]),
-        Position::P2x3([[false, true], [true, true], [false, true]]),
-        Position::P3x2([[false, true, false], [true, true, true]]),
-        Position::P2x3([[true, false], [true, true], [true, false]])
-    )
-);
-
-const ZSHAPE: Shape = Shape::Z(PositionOptions::P2(
-        Position::P3x2([[true, true, false], [false, true, true]]),
-        Position::P2x3([[false, true], [true, true], [true, false]])
-    )
-);
-
-const SHAPES: [Shape; 7] = [ISHAPE, JSHAPE, LSHAPE, OSHAPE, SSHAPE, TSHAPE, ZSHAPE];
-
-struct ShapeState {
-    shape: &'static Shape,
-    position: usize,
-    x: usize,
-    y: usize
-}
+    // The game ends when the goal of the mode is reached
+    fn goal_outcome(&self) -> Option<Outcome> {
+        match self.mode.goal() {
+            Goal::Lines(lines) if self.field.score.lines >= lines => Some(Outcome::GoalReached),
+            Goal::TimeLimit(seconds) if self.frames >= seconds * LOGIC_RATE_HZ => Some(Outcome::TimeUp),
+            Goal::GarbageLines(rows) if self.cheese.as_ref().is_some_and(|cheese| cheese.cleared >= rows) => {
+                Some(Outcome::GoalReached)
+            }
+            _ => None
+        }
+    }
 
-impl ShapeState {
-    fn new(shape: &'static Shape) -> Self {
-        let x = GLASS_WIDTH as usize / 2usize;
-        let y = 0usize;
-        let position = 0usize;
-        ShapeState { shape, position, x, y}
+    fn draw_text(&mut self, text: &str, x: usize, y: usize) {
+        self.screen_canvas.draw_text(text, x, y);
     }
 
-    fn new_position(shape: &'static Shape, position: usize) -> Self {
-        let x = GLASS_WIDTH as usize / 2usize;
-        let y = 0usize;
-        let selected_position: usize = if shape.get_position_count() <= position {
-            0usize
-        } else {
-            position
-        };
+    fn draw_glass_outside(&mut self) {
+        if let Some((x, y)) = self.layout.stats {
+            let lines = match self.mode.goal() {
+                Goal::Lines(goal) => format!("{}/{}", self.field.score.lines, goal),
+                Goal::GarbageLines(goal) => format!("{}/{}", self.cheese.as_ref().map_or(0, |cheese| cheese.cleared), goal),
+                _ => self.field.score.lines.to_string()
+            };
+            // Timed modes count down, the rest count up
+            let (time_label, time) = match self.mode.goal() {
+                Goal::TimeLimit(seconds) => {
+                    (Msg::SidebarTimeLeft, (seconds * 1000).saturating_sub(self.duration_ms()))
+                }
+                _ => (Msg::SidebarTime, self.duration_ms())
+            };
+            let stats = [
+                (Msg::SidebarLines, lines),
+                (Msg::SidebarLevel, self.field.score.level.to_string()),
+                (Msg::SidebarScore, self.field.score.points.to_string()),
+                (time_label, storage::format_duration(time))
+            ];
+            self.draw_text(self.mode.title(), x, y);
+            for (idx, (label, value)) in stats.iter().enumerate() {
+                let line = format!("{} {}", pad_right(tr(*label), 13), value);
+                self.draw_text(&line, x, y + 2 + idx);
+            }
+        }
 
-        ShapeState { shape, position: selected_position, x, y}
+        if let (Some((x, y)), Some(_)) = (self.layout.help, &self.bot) {
+            self.draw_text(tr(Msg::Demo), x, y);
+            self.draw_text(tr(Msg::AnyKeyToExit), x, y + 2);
+        } else if let (Some((x, y)), Some(playback)) = (self.layout.help, &self.playback) {
+            let status = if playback.paused {
+                format!("{} {}", tr(Msg::Replay), tr(Msg::Pause))
+            } else {
+                format!("{} x{}", tr(Msg::Replay), PLAYBACK_SPEEDS[playback.speed_idx])
+            };
+            let help = [
+                status,
+                String::new(),
+                format!("{}: {}", Key::Char(' ').title(), tr(Msg::ActionPause)),
+                format!("{}{}: {}", Key::Left.title(), Key::Right.title(), tr(Msg::Speed)),
+                format!("{}: {}", Key::Down.title(), tr(Msg::Step)),
+                format!("{}: {}", self.settings.key(Action::Quit).title(), Action::Quit.title())
+            ];
+            for (idx, line) in help.iter().enumerate() {
+                self.draw_text(line, x, y + idx);
+            }
+        } else if let (Some((x, y)), true) = (self.layout.help, self.settings.show_help) {
+            // Esc and space come after the keys bound to their actions
+            for (idx, action) in ACTIONS.iter().enumerate() {
+                let mut keys = self.settings.key(*action).title();
+                if let Some(key) = settings::reserved_key(*action) {
+                    keys = format!("{}/{}", keys, key.title());
+                }
+                self.draw_text(&format!("{}: {}", keys, action.title()), x, y + idx);
+            }
+        }
     }
 
-    fn new_random() -> Self {
-        //Get random number from 0 to 6
-        let random_shape_idx = rand::random::<usize>() % SHAPES.len();
-        let random_position = rand::random::<usize>() % SHAPES[random_shape_idx].get_position_count();
-        Self::new_position(&SHAPES[random_shape_idx], random_position)
+    fn draw_too_small(&mut self) {
+        let (min_width, min_height) = Layout::min_size(self.field.glass.width(), self.field.glass.height());
+        let too_small = tr(Msg::TooSmall);
+        let need_size = tr_args(Msg::NeedSize, &[&min_width, &min_height]);
+        let center_x = self.layout.canvas_width / 2;
+        let center_y = self.layout.canvas_height / 2;
+        self.draw_text(too_small, center_x.saturating_sub(text_width(too_small) / 2), center_y.saturating_sub(1));
+        self.draw_text(&need_size, center_x.saturating_sub(text_width(&need_size) / 2), center_y);
+        self.screen_canvas.display();
     }
 
-    fn get_coordinates(&self)->Vec<(usize, usize)> {
-        self.shape.get_shape_coordinates(self.position as usize, self.x as usize, self.y as usize)
+    fn draw_glass(&mut self) {
+        self.field.draw_border(&mut self.screen_canvas, self.layout.glass_left, self.layout.glass_top);
     }
-}
-
 
-#[derive(PartialEq, Clone, Copy)]
-enum GlassPixel {
-    Empty,
-    Figure,
-    Frozen
-}
+    // The falling shape is left out once the game is over, the last one
+    // did not fit anyway
+    fn draw_glass_inside(&mut self, with_shape: bool) {
+        self.field.draw(&mut self.screen_canvas, self.layout.glass_left, self.layout.glass_top, with_shape);
+    }
 
-impl Default for GlassPixel {
-    fn default()->Self {
-        GlassPixel::Empty
+    fn draw_frame(&mut self) {
+        self.draw_glass();
+        self.draw_glass_inside(true);
+        self.draw_glass_outside();
+        self.screen_canvas.display();
     }
-}
 
-pub struct Game {
-    current_shape: ShapeState,
-    screen_canvas: ioscreen::Canvas,
-    glass: [[GlassPixel; GLASS_WIDTH]; GLASS_HEIGHT],
-}
+    fn draw_paused(&mut self) {
+        // Glass contents are hidden while the game is paused
+        self.draw_glass();
+        self.draw_glass_outside();
+        let center_x = self.layout.canvas_width / 2;
+        let center_y = self.layout.canvas_height / 2;
+        match &self.state {
+            GameState::Paused(menu) => menu.draw(&mut self.screen_canvas, center_x, center_y),
+            GameState::Settings(screen) => screen.draw(&mut self.screen_canvas, center_x, center_y),
+            _ => {}
+        }
+        self.screen_canvas.display();
+    }
 
-impl Game {
-    pub fn new()->Self {
-        let mut glass: [[GlassPixel; GLASS_WIDTH]; GLASS_HEIGHT] = Default::default();
-        for t in &mut glass {t.fill(GlassPixel::Empty)}
+    fn duration_ms(&self) -> u64 {
+        self.frames * 1000 / LOGIC_RATE_HZ
+    }
 
-        Game {
-            current_shape: ShapeState::new(&TSHAPE),
-            screen_canvas: ioscreen::Canvas::new(GAME_CANVAS_WIDTH, GAME_CANVAS_HEIGHT),
-            glass
+    fn high_score_entry(&self, name: &str) -> Entry {
+        Entry {
+            name: highscores::clean_name(name),
+            points: self.field.score.points,
+            lines: self.field.score.lines,
+            level: self.field.score.level,
+            duration_ms: self.duration_ms(),
+            date: storage::unix_time(),
+            seed: self.seed
         }
     }
 
-    fn clear_shape_in_glass(&mut self) {
-        // Go through all pixela in glass and replace all Figure pixels with Empty
-        for y in 0..GLASS_HEIGHT {
-            for x in 0..GLASS_WIDTH {
-                if self.glass[y][x] == GlassPixel::Figure {
-                    self.glass[y][x] = GlassPixel::Empty;
-                }
-            }
+    fn finish_game(&mut self, outcome: Outcome) {
+        self.outcome = outcome;
+        if self.playback.is_some() {
+            self.state = GameState::GameOver(None);
+            return;
+        }
+        // The demo goes on with the next game
+        if self.bot.is_some() {
+            self.restart();
+            return;
+        }
+        self.replay.date = storage::unix_time();
+        self.replay.frames = self.frames;
+        self.replay.points = self.field.score.points;
+        self.replay.save();
+
+        // A race against the clock only counts when it is finished, and
+        // games in a glass of another size or with other pieces do not
+        // compete with the rest
+        let default_glass = self.mode.glass_size((DEFAULT_GLASS_WIDTH, DEFAULT_GLASS_HEIGHT));
+        let default_pieces = self.mode.piece_set().unwrap_or(DEFAULT_PIECE_SET);
+        let counts = (self.mode.ranking() != Ranking::Time || outcome == Outcome::GoalReached)
+            && (self.field.glass.width(), self.field.glass.height()) == default_glass
+            && self.field.pieces.name == default_pieces && self.field.pieces.is_builtin();
+        let high_scores = HighScores::load();
+        if counts && high_scores.rank(self.mode, &self.high_score_entry("")).is_some() {
+            self.state = GameState::EnterName(highscores::default_name());
+        } else {
+            self.state = GameState::GameOver(None);
         }
     }
 
-    fn validate_coordinates(coordinates: &Vec<(usize, usize)>) -> bool {
-        // Check if any coordinates are outside the glass
-        for (x, y) in coordinates {
-            if *x >= GLASS_WIDTH || *y >= GLASS_HEIGHT {
-                return false;
-            }
-        }
-        true
+    fn save_record(&mut self, name: &str) {
+        let mut high_scores = HighScores::load();
+        let rank = high_scores.insert(self.mode, self.high_score_entry(name));
+        high_scores.save();
+        self.state = GameState::GameOver(Some(high_scores.table_lines(self.mode, rank)));
     }
 
-    fn freeze_shape(&mut self) {
-        for y in 0..GLASS_HEIGHT {
-            for x in 0..GLASS_WIDTH {
-                if self.glass[y][x] == GlassPixel::Figure {
-                    self.glass[y][x] = GlassPixel::Frozen;
-                }
+    fn draw_game_over(&mut self) {
+        let (left, right) = (self.layout.glass_left, self.layout.glass_right);
+        let center_y = self.layout.glass_top + self.field.glass.height()/2;
+        self.draw_glass();
+        self.draw_glass_inside(false);
+        self.draw_glass_outside();
+        let lines = match &self.state {
+            GameState::EnterName(name) => vec![
+                tr(Msg::NewRecord).to_string(),
+                String::new(),
+                format!("{} {}", tr(Msg::Score), self.field.score.points),
+                format!("{} {}", tr(Msg::Name), pad_right(&format!("{}_", name), highscores::MAX_NAME_LENGTH + 1)),
+                String::new(),
+                tr(Msg::EnterToSave).to_string()
+            ],
+            GameState::GameOver(Some(table)) => {
+                let mut lines = vec![tr(Msg::NewRecord).to_string(), String::new()];
+                lines.extend(table.iter().cloned());
+                lines.push(String::new());
+                lines.push(tr(Msg::PressSpace).to_string());
+                lines
             }
-        }
+            _ => self.summary_lines()
+        };
+        menu::draw_panel(&mut self.screen_canvas, &lines, (right + left)/2, center_y);
+        self.screen_canvas.display();
     }
 
-    fn intersects_frozen_pixels(&self, coordinates: &Vec<(usize, usize)>) -> bool {
-        for y in 0..GLASS_HEIGHT {
-            for x in 0..GLASS_WIDTH {
-                if self.glass[y][x] == GlassPixel::Frozen {
-                    for (x1, y1) in coordinates {
-                        if *x1 == x && *y1 == y {
-                            return true;
-                        }
-                    }
-                }
+    fn summary_lines(&self) -> Vec<String> {
+        let title = match self.outcome {
+            Outcome::ToppedOut => Msg::GameOver,
+            Outcome::GoalReached => Msg::GoalReached,
+            Outcome::TimeUp => Msg::TimeUp
+        };
+        // Labels differ in width between languages, values stay aligned
+        let stat = |msg: Msg, value: String| format!("{}{}", pad_right(tr(msg), 9), pad_left(&value, 8));
+        vec![
+            tr(title).to_string(),
+            String::new(),
+            stat(Msg::Lines, self.field.score.lines.to_string()),
+            stat(Msg::Level, self.field.score.level.to_string()),
+            stat(Msg::Score, self.field.score.points.to_string()),
+            stat(Msg::Pieces, self.field.score.pieces.to_string()),
+            stat(Msg::Time, storage::format_duration(self.duration_ms())),
+            format!("{} {:016x}", tr(Msg::Seed), self.seed),
+            String::new(),
+            tr(Msg::PressSpace).to_string(),
+        ]
+    }
+
+    fn pause(&mut self, selected: usize) {
+        let mut menu = Menu::new(tr(Msg::Pause), PAUSE_ITEMS.iter().map(|item| tr(*item).to_string()).collect());
+        menu.selected = selected;
+        self.state = GameState::Paused(menu);
+        self.pending_actions.clear();
+    }
+
+    // Fresh game with a new seed, a replay starts over instead
+    fn restart(&mut self) {
+        let pieces = match &mut self.playback {
+            Some(playback) => {
+                playback.cursor = 0;
+                self.field.pieces.clone()
             }
-        }
-        false
+            None => {
+                // Glass size and pieces changed in the settings apply from
+                // here on
+                let glass_size = self.mode.glass_size((self.settings.glass_width, self.settings.glass_height));
+                let pieces = mode_pieces(self.mode, &self.settings);
+                self.layout = Layout::new(self.layout.canvas_width, self.layout.canvas_height, glass_size.0, glass_size.1);
+                self.seed = random::new_seed();
+                self.replay = Replay::new(self.mode, self.seed, glass_size, &pieces);
+                pieces
+            }
+        };
+        self.field = replay_field(&self.replay, pieces);
+        self.fill_cheese();
+        self.frames = 0;
+        self.pending_actions.clear();
+        self.state = GameState::Playing;
     }
 
-    fn is_row_full(&self, row: usize)->bool {
-        for x in 0..GLASS_WIDTH {
-            if self.glass[row][x] != GlassPixel::Frozen {
-                return false;
+    // Returns false when the replay should be left
+    fn handle_playback_key(&mut self, key: Key) -> bool {
+        let playback = match &mut self.playback {
+            Some(playback) => playback,
+            None => return true
+        };
+        match (key, self.settings.action(key)) {
+            (_, Some(Action::Quit)) => return false,
+            (Key::Char(' '), _) | (_, Some(Action::Pause)) => playback.paused = !playback.paused,
+            (Key::Left, _) | (_, Some(Action::Left)) => {
+                playback.speed_idx = playback.speed_idx.saturating_sub(1);
+            }
+            (Key::Right, _) | (_, Some(Action::Right)) => {
+                playback.speed_idx = (playback.speed_idx + 1).min(PLAYBACK_SPEEDS.len() - 1);
+            }
+            (Key::Down, _) | (_, Some(Action::SpeedUp)) => {
+                playback.paused = true;
+                playback.steps += 1;
             }
+            _ => {}
         }
         true
     }
 
-    fn explode_rows(&mut self) {
-        let mut new_glass: [[GlassPixel; GLASS_WIDTH]; GLASS_HEIGHT] = Default::default();
-        let mut row_idx = GLASS_HEIGHT - 1;
-        for y in (0..GLASS_HEIGHT).rev() {
-            if !self.is_row_full(y) {
-                new_glass[row_idx] = self.glass[y];
-                if row_idx > 0 {
-                    row_idx -= 1;
+    // Returns false when the game should be left
+    fn handle_key(&mut self, key: Key) -> bool {
+        match &mut self.state {
+            GameState::Playing if self.playback.is_some() => return self.handle_playback_key(key),
+            GameState::Playing if self.bot.is_some() => return false,
+            GameState::Playing => match self.settings.action(key) {
+                Some(Action::Pause) => self.pause(PAUSE_RESUME),
+                // Quitting goes through the pause menu as a confirmation
+                Some(Action::Quit) => self.pause(PAUSE_QUIT),
+                Some(action) => self.pending_actions.push(action),
+                None => {}
+            },
+            GameState::Paused(menu) => match menu.handle_key(key, &self.settings) {
+                MenuEvent::Select(PAUSE_RESUME) | MenuEvent::Back => self.state = GameState::Playing,
+                MenuEvent::Select(PAUSE_RESTART) => self.restart(),
+                MenuEvent::Select(PAUSE_SETTINGS) => self.state = GameState::Settings(SettingsScreen::new(&self.settings)),
+                MenuEvent::Select(PAUSE_QUIT) => return false,
+                MenuEvent::Quit if menu.selected == PAUSE_QUIT => return false,
+                MenuEvent::Quit => menu.selected = PAUSE_QUIT,
+                _ => {}
+            },
+            GameState::Settings(screen) => {
+                if screen.handle_key(key, &mut self.settings) {
+                    self.pause(PAUSE_SETTINGS);
                 }
             }
-        }
-
-        for y in 0..row_idx {
-            new_glass[y].fill(GlassPixel::Empty);
-        }
-
-        self.glass = new_glass;
-    }
-
-    fn update_glass_with_shape(&mut self, key_pressed: Option<char>)->bool {
-        let coordinates = self.current_shape.get_coordinates();
-        let is_valid_coordinates = Self::validate_coordinates(&coordinates);
-        if !is_valid_coordinates {
-            self.freeze_shape();
-            self.current_shape = ShapeState::new_random();
-            return false;
-        }
-
-        if let Some(key) = key_pressed {
-            let old_x = self.current_shape.x;
-            let old_position = self.current_shape.position;
-            match key {
-                '7' => {
-                    if self.current_shape.x > 0 {
-                        self.current_shape.x = self.current_shape.x - 1;
+            GameState::EnterName(name) => match key {
+                Key::Char(ch) => {
+                    let longer = format!("{}{}", name, ch);
+                    if !ch.is_control() && text_width(&longer) <= highscores::MAX_NAME_LENGTH {
+                        *name = longer;
                     }
                 }
-                '8' => {
-                    let new_position = (old_position + 1) % self.current_shape.shape.get_position_count();
-                    self.current_shape.position = new_position;
+                Key::Backspace => {
+                    name.pop();
                 }
-                '9' => {
-                    self.current_shape.x = self.current_shape.x + 1;
+                Key::Enter => {
+                    let name = name.clone();
+                    self.save_record(&name);
                 }
-                _ => {
-
+                // Esc leaves the record out of the table
+                Key::Esc => self.state = GameState::GameOver(None),
+                _ => {}
+            },
+            GameState::GameOver(_) => {
+                if key == Key::Char(' ') {
+                    self.restart();
+                } else if self.settings.action(key) == Some(Action::Quit) {
+                    return false;
                 }
             }
-
-            let new_coordinates = self.current_shape.get_coordinates();
-            let new_is_valid_coordinates = Self::validate_coordinates(&new_coordinates);
-            if !new_is_valid_coordinates {
-                self.current_shape.x = old_x;
-                self.current_shape.position = old_position;
-            }
-            let intersects_frozen_pixels = self.intersects_frozen_pixels(&new_coordinates);
-            if intersects_frozen_pixels {
-                self.current_shape.x = old_x;
-                self.current_shape.position = old_position;
-            }
-        }
-
-        let intersects_frozen_pixels = self.intersects_frozen_pixels(&coordinates);
-        if intersects_frozen_pixels {
-            self.freeze_shape();
-            self.current_shape = ShapeState::new_random();
-            let new_coordinates = self.current_shape.get_coordinates();
-            let intersects_frozen_pixels = self.intersects_frozen_pixels(&new_coordinates);
-            if intersects_frozen_pixels {
-                return true;
-            } else {
-                return false;
-            }
         }
-
-        //Clear shape in glass
-        self.clear_shape_in_glass();
-        for (x, y) in coordinates {
-            self.glass[y][x] = GlassPixel::Figure;
-        }
-        false
-    }
-
-    fn update_glass(&mut self, key: Option<char>)->bool {
-        self.update_glass_with_shape(key)
-    }
-
-    fn glass_lr()-> (usize, usize) {
-        let glass_left = GAME_CANVAS_WIDTH/2 - GLASS_WIDTH;
-        let glass_right: usize = glass_left + GLASS_WIDTH*2;
-        (glass_left, glass_right)
+        true
     }
 
-    fn draw_text(&mut self, text: &str, x: usize, y: usize) {
-        let mut char_x_pos = x;
-        for character in text.chars() {
-            self.screen_canvas.set_char(char_x_pos, y, character);
-            char_x_pos += 1;
+    // Logic frames to play in this screen frame, replays may play faster,
+    // stand still or go frame by frame
+    fn take_tick_frames(&mut self) -> u32 {
+        match &mut self.playback {
+            None => 1,
+            Some(playback) if playback.paused => std::mem::take(&mut playback.steps),
+            Some(playback) => PLAYBACK_SPEEDS[playback.speed_idx]
         }
     }
 
-    fn draw_glass_inside(&mut self) {
-        let (glass_left, _) = Self::glass_lr();
-        for y in 0..GLASS_HEIGHT {
-            for x in 0..GLASS_WIDTH {
-                let (char1, char2) = if self.glass[y][x] != GlassPixel::Empty {('█', '█')} else {(' ', '.')};
-                self.screen_canvas.set_char(glass_left + x*2 + 0, y, char1);
-                self.screen_canvas.set_char(glass_left + x*2 + 1, y, char2);
+    fn logic_frame(&mut self) {
+        let actions = match (&mut self.playback, &mut self.bot) {
+            (Some(playback), _) => self.replay.actions(self.frames, &mut playback.cursor),
+            (None, Some(bot)) => {
+                bot.actions(&self.field.glass, self.field.piece(), self.field.position(), self.field.score.pieces)
+            }
+            (None, None) => {
+                let actions = std::mem::take(&mut self.pending_actions);
+                self.replay.record(self.frames, &actions);
+                actions
             }
+        };
+        self.frames += 1;
+        let mut topped_out = self.field.update(&actions).topped_out;
+        if let (Some(cheese), false) = (&mut self.cheese, topped_out) {
+            // The race keeps the glass topped up until the goal, in the dig
+            // mode garbage rises with time instead
+            let rows = self.mode.cheese_rows(self.field.glass.height());
+            let limit = match self.mode.goal() {
+                Goal::GarbageLines(goal) => goal,
+                _ => 0
+            };
+            topped_out = cheese.update(&mut self.field, rows, limit);
+            let rises = self.mode.garbage_rise_secs().is_some_and(|secs| self.frames.is_multiple_of(secs * LOGIC_RATE_HZ));
+            if rises {
+                topped_out |= cheese.add(&mut self.field, 1);
+            }
+        }
+        if topped_out {
+            self.finish_game(Outcome::ToppedOut);
+        } else if let Some(outcome) = self.goal_outcome() {
+            self.finish_game(outcome);
         }
     }
 
-    fn draw_glass_outside(&mut self) {
-        self.draw_text("ПОЛНЫХ СТРОК: ", 0, 0);
-        self.draw_text("УРОВЕНЬ:      ", 0, 1);
-        self.draw_text("  СЧЕТ:  ", 0, 2);
-
-        let (glass_left, glass_right) = Self::glass_lr();
-        self.draw_text("7: НАЛЕВО   9: НАПРАВО", glass_right + 4, 1);
-        self.draw_text("     8:ПОВОРОТ        ", glass_right + 4, 2);
-        self.draw_text("4:УСКОРИТЬ  5:СБРОСИТЬ", glass_right + 4, 3);
-        self.draw_text("1: ПОКАЗАТЬ  СЛЕДУЮЩУЮ", glass_right + 4, 4);
-        self.draw_text("0:  СТЕРЕТЬ ЭТОТ ТЕКСТ", glass_right + 4, 5);
-        self.draw_text("  ПРОБЕЛ - СБРОСИТЬ   ", glass_right + 4, 6);
+    // The broadcast goes from game to game, the caller keeps it in between
+    pub fn set_broadcast(&mut self, broadcast: Option<Broadcast>) {
+        self.broadcast = broadcast;
     }
 
-    fn draw_glass(&mut self) {
-        let (glass_left, glass_right) = Self::glass_lr();
-        for line_idx in 0..GLASS_HEIGHT {
-            self.screen_canvas.set_char(glass_left - 2, line_idx, '<');
-            self.screen_canvas.set_char(glass_left - 1, line_idx, '!');
-            for dot_x in glass_left..glass_right {
-                if dot_x % 2 == 0 {
-                    self.screen_canvas.set_char(dot_x, line_idx, ' ');
-                } else {
-                    self.screen_canvas.set_char(dot_x, line_idx, '.');
-                }
-            }
-            self.screen_canvas.set_char(glass_right, line_idx, '!');
-            self.screen_canvas.set_char(glass_right + 1, line_idx, '>');
-        }
-        self.screen_canvas.set_char(glass_left - 2, GLASS_HEIGHT, '<');
-        self.screen_canvas.set_char(glass_left - 1, GLASS_HEIGHT, '!');
-        for dot_x in glass_left..glass_right {
-            self.screen_canvas.set_char(dot_x, GLASS_HEIGHT, '=');
-            if dot_x % 2 == 0 {
-                self.screen_canvas.set_char(dot_x, GLASS_HEIGHT + 1, '\\');
-            } else {
-                self.screen_canvas.set_char(dot_x, GLASS_HEIGHT + 1, '/');
-            }
-        }
-        self.screen_canvas.set_char(glass_right, GLASS_HEIGHT, '!');
-        self.screen_canvas.set_char(glass_right + 1, GLASS_HEIGHT, '>');
+    pub fn take_broadcast(&mut self) -> Option<Broadcast> {
+        self.broadcast.take()
     }
 
-    fn draw_frame(&mut self) {
-        self.draw_glass();
-        self.explode_rows();
-        self.draw_glass_inside();
-        self.draw_glass_outside();
-        self.screen_canvas.display();
+    fn update_broadcast(&mut self) {
+        let broadcast = match &mut self.broadcast {
+            Some(broadcast) => broadcast,
+            None => return
+        };
+        let state = match self.state {
+            GameState::Playing => ViewState::Playing,
+            GameState::Paused(_) | GameState::Settings(_) => ViewState::Paused,
+            GameState::EnterName(_) | GameState::GameOver(_) => ViewState::Over
+        };
+        let board = Board {
+            name: broadcast.name().to_string(),
+            lines: self.field.score.lines,
+            incoming: 0,
+            rows: self.field.rows(state == ViewState::Playing),
+            out: state == ViewState::Over && self.outcome == Outcome::ToppedOut
+        };
+        broadcast.update(state, (self.field.glass.width(), self.field.glass.height()), &[board]);
     }
 
-    fn draw_game_over(&mut self) {
-        let (left, right) = Self::glass_lr();
-        let game_over = "ИГРА ОКОНЧЕНА";
-        let press_space = "НАЖМИТЕ ПРОБЕЛ";
-        let start_game_over = ((right + left)/2) - (game_over.chars().count()/2);
-        let start_press_space = ((right + left)/2) - (press_space.chars().count()/2);
-        self.draw_glass();
-        self.draw_glass_outside();
-        self.draw_text(game_over, start_game_over, GAME_CANVAS_HEIGHT/2);
-        self.draw_text(press_space, start_press_space, (GAME_CANVAS_HEIGHT/2) + 1);
-        self.screen_canvas.display();
+    pub fn into_settings(self) -> Settings {
+        self.settings
     }
 
+    // Runs until the player leaves the game
     pub fn game_loop(&mut self) {
         ioscreen::clear_screen();
-        let mut is_game_over = false;
-        self.current_shape = ShapeState::new_random();
-        let mut update_counter: u64 = 0u64;
-        const mov_rate: u64 = MOVE_RATE_MSEC;
-        let mut move_steps = mov_rate/REFRESH_RATE_MSEC;
-        let mut move_steps_changed = 0u64;
-        const speed_increment_step: u64 = SPEED_INCREMENT_STEP / REFRESH_RATE_MSEC;
-
+        let frame_duration = Duration::from_nanos(1_000_000_000 / LOGIC_RATE_HZ);
+        let mut next_frame = Instant::now();
 
         loop {
-            let key = ioscreen::getch();
-            if key == Some('q') {
+            // Keys are collected while waiting for the next logic frame
+            let now = Instant::now();
+            if now < next_frame {
+                if let Some(key) = ioscreen::getch(next_frame - now) {
+                    if !self.handle_key(key) {
+                        return;
+                    }
+                }
+                continue;
+            }
+
+            if ioscreen::quit_requested() {
                 return;
-            } else if key == Some('4') {
-                if move_steps > speed_increment_step {
-                    if move_steps_changed == 0 {
-                        move_steps_changed = move_steps - speed_increment_step;
-                    } else {
-                        if move_steps_changed > speed_increment_step {
-                            move_steps_changed = move_steps_changed - speed_increment_step;
+            }
+
+            self.handle_resize();
+            let mut frames = 0;
+            while next_frame <= now && frames < MAX_CATCH_UP_FRAMES {
+                // The game stands still while paused and until the glass
+                // fits the terminal again
+                if let (GameState::Playing, false) = (&self.state, self.layout.too_small) {
+                    for _ in 0..self.take_tick_frames() {
+                        self.logic_frame();
+                        if !matches!(self.state, GameState::Playing) {
+                            break;
                         }
                     }
                 }
+                next_frame += frame_duration;
+                frames += 1;
+            }
+            if next_frame <= now {
+                next_frame = now + frame_duration;
             }
+
             self.screen_canvas.clear();
-            if is_game_over == false {
-                is_game_over = self.update_glass(key);
-                self.draw_frame();
-                if update_counter % move_steps == 0 {
-                    self.current_shape.y += 1;
-                    if move_steps_changed > 0 {
-                        move_steps = move_steps_changed;
-                        move_steps_changed = 0;
-                    }
-                }
+            if self.layout.too_small {
+                self.draw_too_small();
             } else {
-                self.draw_game_over();
+                match self.state {
+                    GameState::Playing => self.draw_frame(),
+                    GameState::Paused(_) | GameState::Settings(_) => self.draw_paused(),
+                    GameState::EnterName(_) | GameState::GameOver(_) => self.draw_game_over()
+                }
             }
-            update_counter += 1;
-        }   
+            self.update_broadcast();
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dig_garbage_rises_on_schedule() {
+        let pieces = PieceSet::standard();
+        let replay = Replay::new(GameMode::Dig, 1, (10, 20), &pieces);
+        let mut game = Game::with_replay(replay, pieces, Settings::default());
+        game.fill_cheese();
+        let rows = GameMode::Dig.cheese_rows(20);
+        // The falling shapes may clear a garbage row now and then
+        let added = |game: &Game| game.field.garbage_rows() + game.cheese.as_ref().unwrap().cleared;
+        assert_eq!(added(&game), rows);
+        let frames = GameMode::Dig.garbage_rise_secs().unwrap() * LOGIC_RATE_HZ;
+        for _ in 1..frames {
+            game.logic_frame();
+        }
+        assert_eq!(added(&game), rows);
+        game.logic_frame();
+        assert_eq!(added(&game), rows + 1);
+        for _ in 0..frames {
+            game.logic_frame();
+        }
+        assert_eq!(added(&game), rows + 2);
+        assert!(matches!(game.state, GameState::Playing));
+    }
+}
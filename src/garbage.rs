@@ -0,0 +1,236 @@
+// Garbage rows of versus games: rows cleared in one glass are sent to
+// another one and come up from its bottom
+use crate::field::Field;
+use crate::random::Random;
+
+// Garbage rows sent for 0, 1, 2, 3 and 4 rows cleared at once
+const ATTACK_ROWS: [u32; 5] = [0, 0, 1, 2, 4];
+// Holes of garbage rows come from a sequence of their own, the pieces stay
+// the same whatever garbage there is
+const GARBAGE_SEED_MIX: u64 = 0x6A4BA6E5EED;
+const CHEESE_SEED_MIX: u64 = 0xC4EE5EC4EE5E;
+
+pub fn attack_rows(rows_cleared: u32) -> u32 {
+    ATTACK_ROWS[(rows_cleared as usize).min(ATTACK_ROWS.len() - 1)]
+}
+
+// Garbage on its way to one glass
+pub struct Garbage {
+    // One entry per attack, every attack comes up with a hole column of its own
+    incoming: Vec<u32>,
+    random: Random
+}
+
+impl Garbage {
+    // Glasses of a game get the same seed and so the same holes
+    pub fn new(seed: u64) -> Self {
+        Garbage {incoming: Vec::new(), random: Random::new(seed ^ GARBAGE_SEED_MIX)}
+    }
+
+    pub fn push(&mut self, rows: u32) {
+        if rows > 0 {
+            self.incoming.push(rows);
+        }
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.incoming.iter().sum()
+    }
+
+    // An attack first cancels the garbage coming to the attacker, the rest
+    // goes on to the other glass
+    fn cancel(&mut self, mut attack: u32) -> u32 {
+        while attack > 0 && !self.incoming.is_empty() {
+            let cancelled = attack.min(self.incoming[0]);
+            attack -= cancelled;
+            self.incoming[0] -= cancelled;
+            if self.incoming[0] == 0 {
+                self.incoming.remove(0);
+            }
+        }
+        attack
+    }
+
+    // Puts all the waiting garbage into the glass, true when it overflows
+    fn take(&mut self, field: &mut Field) -> bool {
+        let width = field.glass.width();
+        let mut topped_out = false;
+        for rows in std::mem::take(&mut self.incoming) {
+            let hole = self.random.below(width);
+            topped_out |= field.add_garbage(rows as usize, hole);
+        }
+        topped_out
+    }
+
+    // After a shape of the field locked. Returns the rows to send on and
+    // true when the garbage made the glass overflow. Garbage waits while
+    // the player keeps clearing rows
+    pub fn shape_locked(&mut self, field: &mut Field, rows_cleared: u32) -> (u32, bool) {
+        let attack = self.cancel(attack_rows(rows_cleared));
+        let topped_out = rows_cleared == 0 && self.take(field);
+        (attack, topped_out)
+    }
+}
+
+// Garbage of the dig modes, every row comes with a hole of its own
+pub struct Cheese {
+    random: Random,
+    // Garbage rows put into the glass so far, and the ones cleared
+    added: u32,
+    pub cleared: u32
+}
+
+impl Cheese {
+    // Starts the glass with the given rows, which have to fit
+    pub fn new(seed: u64, field: &mut Field, rows: u32) -> Self {
+        let mut cheese = Cheese {random: Random::new(seed ^ CHEESE_SEED_MIX), added: 0, cleared: 0};
+        cheese.add(field, rows);
+        cheese
+    }
+
+    // Continues a saved game, the holes to come are the ones the game would
+    // have had anyway
+    pub fn resume(seed: u64, field: &Field, cleared: u32) -> Self {
+        let mut random = Random::new(seed ^ CHEESE_SEED_MIX);
+        let added = cleared + field.garbage_rows();
+        for _ in 0..added {
+            random.next_u64();
+        }
+        Cheese {random, added, cleared}
+    }
+
+    // True when the glass overflows
+    pub fn add(&mut self, field: &mut Field, rows: u32) -> bool {
+        let width = field.glass.width();
+        let mut topped_out = false;
+        for _ in 0..rows {
+            let hole = self.random.below(width);
+            self.added += 1;
+            topped_out |= field.add_garbage(1, hole);
+        }
+        topped_out
+    }
+
+    // After the field updated: the garbage rows missing were cleared. Tops
+    // the glass up to the given rows while the total stays within the limit
+    pub fn update(&mut self, field: &mut Field, rows: u32, limit: u32) -> bool {
+        self.cleared = self.added - field.garbage_rows();
+        let missing = rows.saturating_sub(field.garbage_rows()).min(limit.saturating_sub(self.added));
+        self.add(field, missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::{ShapeState, GARBAGE_COLOR};
+    use crate::gravity::GravityCurve;
+    use crate::piece::PieceSet;
+    use crate::settings::Action;
+
+    fn field() -> Field {
+        Field::new(PieceSet::standard(), 1, (10, 20), GravityCurve::Guideline)
+    }
+
+    #[test]
+    fn bigger_clears_attack_harder() {
+        let attacks: Vec<u32> = (0..6).map(attack_rows).collect();
+        assert_eq!(attacks, [0, 0, 1, 2, 4, 4]);
+    }
+
+    #[test]
+    fn attacks_cancel_incoming_rows_first() {
+        let mut garbage = Garbage::new(1);
+        garbage.push(2);
+        garbage.push(0);
+        garbage.push(3);
+        assert_eq!(garbage.rows(), 5);
+        assert_eq!(garbage.cancel(4), 0);
+        assert_eq!(garbage.incoming, [1]);
+        // Whatever is left over goes on to the opponent
+        assert_eq!(garbage.cancel(3), 2);
+        assert_eq!(garbage.rows(), 0);
+    }
+
+    #[test]
+    fn garbage_comes_up_when_a_shape_locks_without_clearing() {
+        let mut field = field();
+        let mut garbage = Garbage::new(1);
+        garbage.push(2);
+        garbage.push(1);
+
+        // Clearing rows holds the garbage back, four rows cancel two of it
+        assert_eq!(garbage.shape_locked(&mut field, 1), (0, false));
+        assert_eq!(garbage.rows(), 3);
+        assert_eq!(garbage.shape_locked(&mut field, 4), (1, false));
+        assert_eq!(garbage.rows(), 0);
+
+        garbage.push(2);
+        garbage.push(1);
+        assert_eq!(garbage.shape_locked(&mut field, 0), (0, false));
+        assert_eq!(garbage.rows(), 0);
+        assert_eq!(field.garbage_rows(), 3);
+        // Every attack has a hole of its own, the rows of one share it
+        let hole = |y: usize| (0..10).find(|x| !field.glass.is_filled(*x, y)).unwrap();
+        assert_eq!(hole(17), hole(18));
+    }
+
+    #[test]
+    fn garbage_overflowing_the_glass_tops_it_out() {
+        let mut field = field();
+        let mut garbage = Garbage::new(1);
+        garbage.push(20);
+        assert_eq!(garbage.shape_locked(&mut field, 0), (0, true));
+    }
+
+    // Fills the hole of the bottom row and drops a shape to clear it
+    fn clear_bottom_row(field: &mut Field) {
+        let y = field.glass.height() - 1;
+        let hole = (0..field.glass.width()).find(|x| !field.glass.is_filled(*x, y)).unwrap();
+        field.glass.fill(hole, y);
+        assert_eq!(field.update(&[Action::Drop]).rows_cleared, 1);
+    }
+
+    #[test]
+    fn cheese_is_topped_up_within_its_limit() {
+        let mut field = field();
+        let mut cheese = Cheese::new(1, &mut field, 10);
+        assert_eq!(field.garbage_rows(), 10);
+
+        clear_bottom_row(&mut field);
+        assert_eq!(field.garbage_rows(), 9);
+        assert!(!cheese.update(&mut field, 10, 12));
+        assert_eq!(cheese.cleared, 1);
+        assert_eq!(field.garbage_rows(), 10);
+
+        // One row more would go past the limit of twelve rows
+        clear_bottom_row(&mut field);
+        cheese.update(&mut field, 10, 12);
+        assert_eq!(field.garbage_rows(), 10);
+        clear_bottom_row(&mut field);
+        cheese.update(&mut field, 10, 12);
+        assert_eq!(cheese.cleared, 3);
+        assert_eq!(field.garbage_rows(), 9);
+    }
+
+    #[test]
+    fn resumed_cheese_counts_only_garbage_rows() {
+        let mut pieces = PieceSet::standard();
+        let o = pieces.piece_index("O").unwrap();
+        pieces.pieces[o].color = Some(GARBAGE_COLOR);
+        let mut field = Field::new(pieces, 1, (4, 10), GravityCurve::Guideline);
+        let mut cheese = Cheese::new(1, &mut field, 5);
+        clear_bottom_row(&mut field);
+        cheese.update(&mut field, 5, 18);
+
+        // A grey O on top looks like garbage but is not
+        field.shape = ShapeState {piece: o, position: 0, x: 1, y: 0};
+        field.update(&[Action::Drop]);
+        let mut resumed = Cheese::resume(1, &field, cheese.cleared);
+        assert!(!resumed.update(&mut field, 5, 18));
+        assert_eq!(resumed.cleared, 1);
+        assert_eq!(field.garbage_rows(), 5);
+        // The holes to come are the ones of the game before
+        assert_eq!(resumed.random.next_u64(), cheese.random.next_u64());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::game::LOGIC_RATE_HZ;
+
+// Gravity is counted in 1/GRAVITY_UNIT cells per logic frame
+pub const GRAVITY_UNIT: u32 = 65536;
+// 20G, the shape falls to the floor on the frame it appears
+pub const MAX_GRAVITY: u32 = 20 * GRAVITY_UNIT;
+
+// NES, logic frames per row starting from the level
+const NES_FRAMES_PER_ROW: [(u32, u32); 15] = [
+    (0, 48), (1, 43), (2, 38), (3, 33), (4, 28), (5, 23), (6, 18), (7, 13), (8, 8),
+    (9, 6), (10, 5), (13, 4), (16, 3), (19, 2), (29, 1)
+];
+
+// TGM, internal gravity in 1/256 G starting from the level
+const TGM_INTERNAL_GRAVITY: [(u32, u32); 30] = [
+    (0, 4), (30, 6), (35, 8), (40, 10), (50, 12), (60, 16), (70, 32), (80, 48),
+    (90, 64), (100, 80), (120, 96), (140, 112), (160, 128), (170, 144), (200, 4),
+    (220, 32), (230, 64), (233, 96), (236, 128), (239, 160), (243, 192), (247, 224),
+    (251, 256), (300, 512), (330, 768), (360, 1024), (400, 1280), (420, 1024),
+    (450, 768), (500, 5120)
+];
+
+// Level past which the guideline formula stops speeding up
+const GUIDELINE_MAX_LEVEL: u32 = 20;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Leveling {
+    // Next level every ten lines
+    PerTenLines,
+    // Next level for every shape and every cleared line, shapes do not
+    // move the level past the end of a section
+    PerPieceAndLine
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GravityCurve {
+    // Seconds per row = (0.8 - (level - 1) * 0.007) ^ (level - 1)
+    Guideline,
+    // Frames per row table of the NES version
+    Nes,
+    // Internal gravity table of Tetris The Grand Master
+    Tgm
+}
+
+impl GravityCurve {
+    // Name used in replays
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Guideline => "guideline",
+            Self::Nes => "nes",
+            Self::Tgm => "tgm"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Guideline, Self::Nes, Self::Tgm].into_iter().find(|curve| curve.name() == name)
+    }
+
+    pub fn first_level(&self) -> u32 {
+        match self {
+            Self::Guideline => 1,
+            Self::Nes | Self::Tgm => 0
+        }
+    }
+
+    pub fn leveling(&self) -> Leveling {
+        match self {
+            Self::Guideline | Self::Nes => Leveling::PerTenLines,
+            Self::Tgm => Leveling::PerPieceAndLine
+        }
+    }
+
+    pub fn gravity(&self, level: u32) -> u32 {
+        let gravity = match self {
+            Self::Guideline => {
+                let step = (level.clamp(1, GUIDELINE_MAX_LEVEL) - 1) as f64;
+                let seconds_per_row = (0.8 - step * 0.007).powf(step);
+                (GRAVITY_UNIT as f64 / (seconds_per_row * LOGIC_RATE_HZ as f64)) as u32
+            }
+            Self::Nes => GRAVITY_UNIT / table_value(&NES_FRAMES_PER_ROW, level),
+            Self::Tgm => table_value(&TGM_INTERNAL_GRAVITY, level) * (GRAVITY_UNIT / 256)
+        };
+        gravity.min(MAX_GRAVITY)
+    }
+}
+
+fn table_value(table: &[(u32, u32)], level: u32) -> u32 {
+    let mut value = table[0].1;
+    for (from_level, level_value) in table {
+        if *from_level > level {
+            break;
+        }
+        value = *level_value;
+    }
+    value
+}
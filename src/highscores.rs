@@ -0,0 +1,206 @@
+use crate::i18n::{tr, Msg};
+use crate::ioscreen::{pad_left, pad_right, text_width};
+use crate::mode::{GameMode, Ranking};
+use crate::storage;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const HIGH_SCORES_FILE: &str = "highscores.tsv";
+const HEADER: &str = "# rustris high scores v2";
+// Marathon was an endless game in files of the first version, its entries
+// belong to the endless mode now that marathon has a goal
+const V1_HEADER: &str = "# rustris high scores v1";
+// Entries kept per game mode
+pub const MAX_ENTRIES: usize = 10;
+// Columns a name takes on the screen
+pub const MAX_NAME_LENGTH: usize = 12;
+
+#[derive(Clone)]
+pub struct Entry {
+    pub name: String,
+    pub points: u64,
+    pub lines: u32,
+    pub level: u32,
+    pub duration_ms: u64,
+    // Unix time the game ended at
+    pub date: u64,
+    pub seed: u64
+}
+
+impl Entry {
+    fn to_line(&self, mode: &str) -> String {
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:016x}", mode, self.name, self.points, self.lines,
+                self.level, self.duration_ms, self.date, self.seed)
+    }
+
+    fn from_line(line: &str) -> Option<(String, Entry)> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 8 {
+            return None;
+        }
+        let entry = Entry {
+            name: fields[1].to_string(),
+            points: fields[2].parse().ok()?,
+            lines: fields[3].parse().ok()?,
+            level: fields[4].parse().ok()?,
+            duration_ms: fields[5].parse().ok()?,
+            date: fields[6].parse().ok()?,
+            seed: u64::from_str_radix(fields[7], 16).ok()?
+        };
+        Some((fields[0].to_string(), entry))
+    }
+
+    pub fn duration_text(&self) -> String {
+        storage::format_duration(self.duration_ms)
+    }
+}
+
+// Name offered for a new record
+pub fn default_name() -> String {
+    let name = env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_default();
+    clean_name(&name)
+}
+
+// Tabs and line breaks would break the file format
+pub fn clean_name(name: &str) -> String {
+    let mut clean = String::new();
+    for ch in name.trim().chars().filter(|ch| !ch.is_control()) {
+        clean.push(ch);
+        if text_width(&clean) > MAX_NAME_LENGTH {
+            clean.pop();
+            break;
+        }
+    }
+    clean
+}
+
+#[derive(Default)]
+pub struct HighScores {
+    tables: Vec<(String, Vec<Entry>)>
+}
+
+impl HighScores {
+    fn path() -> Option<PathBuf> {
+        storage::data_dir().map(|dir| dir.join(HIGH_SCORES_FILE))
+    }
+
+    pub fn load() -> Self {
+        let text = Self::path().and_then(|path| fs::read_to_string(path).ok()).unwrap_or_default();
+        Self::parse(&text)
+    }
+
+    // Lines which can not be read are skipped
+    fn parse(text: &str) -> Self {
+        let mut high_scores = HighScores::default();
+        let first_version = text.lines().next() == Some(V1_HEADER);
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            if let Some((mode_name, entry)) = Entry::from_line(line) {
+                let mode = match GameMode::from_name(&mode_name) {
+                    Some(GameMode::Marathon) if first_version => Some(GameMode::Endless),
+                    mode => mode
+                };
+                if let Some(mode) = mode {
+                    high_scores.insert(mode, entry);
+                }
+            }
+        }
+        high_scores
+    }
+
+    pub fn save(&self) {
+        let mut text = String::from(HEADER);
+        text.push('\n');
+        for (mode_name, entries) in &self.tables {
+            for entry in entries {
+                text += &entry.to_line(mode_name);
+                text.push('\n');
+            }
+        }
+        if let Some(path) = Self::path() {
+            let _ = storage::write_atomic(&path, text.as_bytes());
+        }
+    }
+
+    pub fn table(&self, mode: GameMode) -> &[Entry] {
+        self.tables.iter()
+            .find(|(mode_name, _)| mode_name == mode.name())
+            .map_or(&[], |(_, entries)| entries.as_slice())
+    }
+
+    // Place the entry would take in the table, None if it does not get in
+    pub fn rank(&self, mode: GameMode, entry: &Entry) -> Option<usize> {
+        let table = self.table(mode);
+        let rank = match mode.ranking() {
+            Ranking::Points if entry.points == 0 => return None,
+            Ranking::Points => table.iter().take_while(|other| other.points >= entry.points).count(),
+            Ranking::Time => table.iter().take_while(|other| other.duration_ms <= entry.duration_ms).count()
+        };
+        if rank < MAX_ENTRIES {
+            Some(rank)
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&mut self, mode: GameMode, entry: Entry) -> Option<usize> {
+        let rank = self.rank(mode, &entry)?;
+        let table_idx = match self.tables.iter().position(|(mode_name, _)| mode_name == mode.name()) {
+            Some(idx) => idx,
+            None => {
+                self.tables.push((mode.name().to_string(), Vec::new()));
+                self.tables.len() - 1
+            }
+        };
+        let entries = &mut self.tables[table_idx].1;
+        entries.insert(rank, entry);
+        entries.truncate(MAX_ENTRIES);
+        Some(rank)
+    }
+
+    // Lines of the table for the screen, the highlighted entry is marked
+    pub fn table_lines(&self, mode: GameMode, highlight: Option<usize>) -> Vec<String> {
+        let table = self.table(mode);
+        if table.is_empty() {
+            return vec![tr(Msg::NoRecords).to_string()];
+        }
+        // Names and translated headers may hold double width characters,
+        // so they are padded by the screen width rather than by chars
+        let mut lines = vec![format!("  {:>2} {} {} {} {} {} {}", "#",
+                                     pad_right(tr(Msg::ColumnName), MAX_NAME_LENGTH),
+                                     pad_left(tr(Msg::ColumnScore), 8), pad_left(tr(Msg::ColumnLines), 5),
+                                     pad_left(tr(Msg::ColumnLevel), 4), pad_left(tr(Msg::ColumnTime), 7),
+                                     pad_right(tr(Msg::ColumnDate), 10))];
+        for (idx, entry) in table.iter().enumerate() {
+            let marker = if highlight == Some(idx) {">"} else {" "};
+            lines.push(format!("{} {:>2} {} {:>8} {:>5} {:>4} {:>7} {:<10}", marker, idx + 1,
+                               pad_right(&entry.name, MAX_NAME_LENGTH), entry.points, entry.lines, entry.level,
+                               entry.duration_text(), storage::format_date(entry.date)));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = "ann\t1200\t12\t2\t90000\t1700000000\t00000000000000ff";
+
+    #[test]
+    fn first_version_marathon_scores_go_to_endless() {
+        let text = format!("{}\nmarathon\t{}\nclassic\t{}\n", V1_HEADER, ENTRY, ENTRY);
+        let high_scores = HighScores::parse(&text);
+        assert!(high_scores.table(GameMode::Marathon).is_empty());
+        assert_eq!(high_scores.table(GameMode::Endless).len(), 1);
+        assert_eq!(high_scores.table(GameMode::Classic).len(), 1);
+    }
+
+    #[test]
+    fn marathon_scores_stay_in_marathon() {
+        let text = format!("{}\nmarathon\t{}\n", HEADER, ENTRY);
+        let high_scores = HighScores::parse(&text);
+        assert_eq!(high_scores.table(GameMode::Marathon)[0].points, 1200);
+        assert!(high_scores.table(GameMode::Endless).is_empty());
+    }
+}
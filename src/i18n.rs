@@ -0,0 +1,303 @@
+use std::env;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Language {
+    Russian,
+    English
+}
+
+pub const LANGUAGES: [Language; 2] = [Language::Russian, Language::English];
+
+static LANGUAGE: AtomicU8 = AtomicU8::new(0);
+
+impl Language {
+    // Name used in configuration files
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Russian => "ru",
+            Self::English => "en"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        LANGUAGES.iter().find(|language| language.name() == name).copied()
+    }
+
+    // Every language is called the way its speakers call it
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::Russian => "РУССКИЙ",
+            Self::English => "ENGLISH"
+        }
+    }
+
+    // Language of the locale set in LC_ALL, LC_MESSAGES or LANG. Russian
+    // is the language the game always spoke, so it stays when nothing is set
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty());
+        match locale {
+            Some(locale) => {
+                let code = locale.split(['_', '.', '@']).next().unwrap_or("");
+                Self::from_name(code).unwrap_or(if code == "C" || code == "POSIX" {
+                    Self::Russian
+                } else {
+                    Self::English
+                })
+            }
+            None => Self::Russian
+        }
+    }
+}
+
+pub fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::SeqCst);
+}
+
+pub fn language() -> Language {
+    LANGUAGES[LANGUAGE.load(Ordering::SeqCst) as usize]
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Msg {
+    MainMenu,
+    Continue,
+    Play,
+    HighScores,
+    Replays,
+    Settings,
+    Controls,
+    Quit,
+    Back,
+    GameMode,
+    HighScoresOf,
+    Pause,
+    Resume,
+    Restart,
+    NoReplays,
+    PiecesChanged,
+    Replay,
+    Speed,
+    Step,
+    SidebarLines,
+    SidebarLevel,
+    SidebarScore,
+    SidebarTime,
+    SidebarTimeLeft,
+    TooSmall,
+    NeedSize,
+    GameOver,
+    GoalReached,
+    TimeUp,
+    PressSpace,
+    NewRecord,
+    Name,
+    EnterToSave,
+    Lines,
+    Level,
+    Score,
+    Pieces,
+    Time,
+    Seed,
+    NoRecords,
+    ColumnName,
+    ColumnScore,
+    ColumnLines,
+    ColumnLevel,
+    ColumnTime,
+    ColumnDate,
+    Marathon,
+    Endless,
+    Sprint,
+    Ultra,
+    Classic,
+    Master,
+    Pentomino,
+    Extras,
+    Cheese,
+    Dig,
+    ActionLeft,
+    ActionRight,
+    ActionRotate,
+    ActionSpeedUp,
+    ActionDrop,
+    ActionPause,
+    ActionQuit,
+    Help,
+    Yes,
+    No,
+    Language,
+    LanguageAuto,
+    GlassWidth,
+    GlassHeight,
+    PieceSet,
+    Demo,
+    AnyKeyToExit,
+    Versus,
+    You,
+    Cpu,
+    YouWin,
+    YouLose,
+    Sent,
+    TwoPlayers,
+    PlayerNumber,
+    PlayerWins,
+    SplitGarbage,
+    SplitAction,
+    NetworkGame,
+    HostPort,
+    Connecting,
+    ReadyMark,
+    SpaceToReady,
+    Out,
+    NamedWinner,
+    Draw,
+    ConnectionLost,
+    OtherVersion,
+    GameFull,
+    GameBusy,
+    NoPieceSet,
+    OtherPieceSet,
+    NoGlassSize,
+    LeaveGame,
+    Stay,
+    NoPause,
+    Spectating,
+    WaitingForGame,
+    KeySpace,
+    KeyEnter
+}
+
+impl Msg {
+    // Texts in the order of LANGUAGES
+    fn texts(&self) -> [&'static str; 2] {
+        match self {
+            Self::MainMenu => ["ГЛАВНОЕ МЕНЮ", "MAIN MENU"],
+            Self::Continue => ["ПРОДОЛЖИТЬ ИГРУ", "CONTINUE"],
+            Self::Play => ["ИГРАТЬ", "PLAY"],
+            Self::HighScores => ["РЕКОРДЫ", "HIGH SCORES"],
+            Self::Replays => ["ПОВТОРЫ", "REPLAYS"],
+            Self::Settings => ["НАСТРОЙКИ", "SETTINGS"],
+            Self::Controls => ["УПРАВЛЕНИЕ", "CONTROLS"],
+            Self::Quit => ["ВЫХОД", "QUIT"],
+            Self::Back => ["НАЗАД", "BACK"],
+            Self::GameMode => ["РЕЖИМ", "GAME MODE"],
+            Self::HighScoresOf => ["РЕКОРДЫ: {}", "HIGH SCORES: {}"],
+            Self::Pause => ["ПАУЗА", "PAUSED"],
+            Self::Resume => ["ПРОДОЛЖИТЬ", "RESUME"],
+            Self::Restart => ["ЗАНОВО", "RESTART"],
+            Self::NoReplays => ["ПОВТОРОВ ПОКА НЕТ", "NO REPLAYS YET"],
+            Self::PiecesChanged => ["ФИГУРЫ ИЗМЕНИЛИСЬ", "PIECES CHANGED"],
+            Self::Replay => ["ПОВТОР", "REPLAY"],
+            Self::Speed => ["СКОРОСТЬ", "SPEED"],
+            Self::Step => ["КАДР", "STEP"],
+            Self::SidebarLines => ["ПОЛНЫХ СТРОК:", "LINES:"],
+            Self::SidebarLevel => ["УРОВЕНЬ:", "LEVEL:"],
+            Self::SidebarScore => ["СЧЕТ:", "SCORE:"],
+            Self::SidebarTime => ["ВРЕМЯ:", "TIME:"],
+            Self::SidebarTimeLeft => ["ОСТАЛОСЬ:", "TIME LEFT:"],
+            Self::TooSmall => ["ТЕРМИНАЛ СЛИШКОМ МАЛ", "TERMINAL TOO SMALL"],
+            Self::NeedSize => ["НУЖНО {}x{}", "NEED {}x{}"],
+            Self::GameOver => ["ИГРА ОКОНЧЕНА", "GAME OVER"],
+            Self::GoalReached => ["ЦЕЛЬ ДОСТИГНУТА!", "GOAL REACHED!"],
+            Self::TimeUp => ["ВРЕМЯ ВЫШЛО", "TIME IS UP"],
+            Self::PressSpace => ["НАЖМИТЕ ПРОБЕЛ", "PRESS SPACE"],
+            Self::NewRecord => ["НОВЫЙ РЕКОРД!", "NEW HIGH SCORE!"],
+            Self::Name => ["ИМЯ:", "NAME:"],
+            Self::EnterToSave => ["ВВОД - СОХРАНИТЬ", "ENTER - SAVE"],
+            Self::Lines => ["СТРОК:", "LINES:"],
+            Self::Level => ["УРОВЕНЬ:", "LEVEL:"],
+            Self::Score => ["СЧЕТ:", "SCORE:"],
+            Self::Pieces => ["ФИГУР:", "PIECES:"],
+            Self::Time => ["ВРЕМЯ:", "TIME:"],
+            Self::Seed => ["СИД:", "SEED:"],
+            Self::NoRecords => ["РЕКОРДОВ ПОКА НЕТ", "NO HIGH SCORES YET"],
+            Self::ColumnName => ["ИМЯ", "NAME"],
+            Self::ColumnScore => ["СЧЕТ", "SCORE"],
+            Self::ColumnLines => ["СТРОК", "LINES"],
+            Self::ColumnLevel => ["УР.", "LV."],
+            Self::ColumnTime => ["ВРЕМЯ", "TIME"],
+            Self::ColumnDate => ["ДАТА", "DATE"],
+            Self::Marathon => ["МАРАФОН", "MARATHON"],
+            Self::Endless => ["БЕЗ КОНЦА", "ENDLESS"],
+            Self::Sprint => ["СПРИНТ 40", "SPRINT 40L"],
+            Self::Ultra => ["УЛЬТРА 2 МИН", "ULTRA 2 MIN"],
+            Self::Classic => ["КЛАССИКА", "CLASSIC"],
+            Self::Master => ["МАСТЕР", "MASTER"],
+            Self::Pentomino => ["ПЕНТАМИНО", "PENTOMINO"],
+            Self::Extras => ["ТЕТРАМИНО+", "TETROMINO+"],
+            Self::Cheese => ["СЫР 18", "CHEESE 18L"],
+            Self::Dig => ["РАСКОПКИ", "DIG"],
+            Self::ActionLeft => ["НАЛЕВО", "LEFT"],
+            Self::ActionRight => ["НАПРАВО", "RIGHT"],
+            Self::ActionRotate => ["ПОВОРОТ", "ROTATE"],
+            Self::ActionSpeedUp => ["УСКОРИТЬ", "SPEED UP"],
+            Self::ActionDrop => ["СБРОСИТЬ", "DROP"],
+            Self::ActionPause => ["ПАУЗА", "PAUSE"],
+            Self::ActionQuit => ["ВЫХОД", "QUIT"],
+            Self::Help => ["ПОДСКАЗКА", "HELP"],
+            Self::Yes => ["ДА", "YES"],
+            Self::No => ["НЕТ", "NO"],
+            Self::Language => ["ЯЗЫК", "LANGUAGE"],
+            Self::LanguageAuto => ["АВТО", "AUTO"],
+            Self::GlassWidth => ["ШИРИНА СТАКАНА", "GLASS WIDTH"],
+            Self::GlassHeight => ["ВЫСОТА СТАКАНА", "GLASS HEIGHT"],
+            Self::PieceSet => ["ФИГУРЫ", "PIECES"],
+            Self::Demo => ["ДЕМО", "DEMO"],
+            Self::AnyKeyToExit => ["ЛЮБАЯ КЛАВИША: ВЫХОД", "ANY KEY: EXIT"],
+            Self::Versus => ["ПРОТИВ КОМПЬЮТЕРА", "VERSUS CPU"],
+            Self::You => ["ВЫ", "YOU"],
+            Self::Cpu => ["КОМПЬЮТЕР", "CPU"],
+            Self::YouWin => ["ПОБЕДА", "YOU WIN"],
+            Self::YouLose => ["ПОРАЖЕНИЕ", "YOU LOSE"],
+            Self::Sent => ["ОТПРАВЛЕНО:", "SENT:"],
+            Self::TwoPlayers => ["ДВА ИГРОКА", "TWO PLAYERS"],
+            Self::PlayerNumber => ["ИГРОК {}", "PLAYER {}"],
+            Self::PlayerWins => ["ПОБЕДИЛ ИГРОК {}", "PLAYER {} WINS"],
+            Self::SplitGarbage => ["МУСОР ВДВОЁМ", "2P GARBAGE"],
+            Self::SplitAction => ["{} ВДВОЁМ", "2P {}"],
+            Self::NetworkGame => ["СЕТЕВАЯ ИГРА", "NETWORK GAME"],
+            Self::HostPort => ["ПОРТ {}", "PORT {}"],
+            Self::Connecting => ["ПОДКЛЮЧЕНИЕ...", "CONNECTING..."],
+            Self::ReadyMark => ["ГОТОВ", "READY"],
+            Self::SpaceToReady => ["ПРОБЕЛ: ГОТОВ", "SPACE: READY"],
+            Self::Out => ["ВЫБЫЛ", "OUT"],
+            Self::NamedWinner => ["ПОБЕДИЛ {}", "{} WINS"],
+            Self::Draw => ["НИЧЬЯ", "DRAW"],
+            Self::ConnectionLost => ["СОЕДИНЕНИЕ ПОТЕРЯНО", "CONNECTION LOST"],
+            Self::OtherVersion => ["ДРУГАЯ ВЕРСИЯ ПРОТОКОЛА", "OTHER PROTOCOL VERSION"],
+            Self::GameFull => ["МЕСТ НЕТ", "NO ROOM LEFT"],
+            Self::GameBusy => ["ИГРА УЖЕ ИДЁТ", "GAME IN PROGRESS"],
+            Self::NoPieceSet => ["НЕТ НАБОРА ФИГУР {}", "NO PIECE SET {}"],
+            Self::OtherPieceSet => ["ДРУГОЙ НАБОР ФИГУР {}", "OTHER PIECE SET {}"],
+            Self::NoGlassSize => ["НЕТ СТАКАНА {}x{}", "NO {}x{} GLASS"],
+            Self::LeaveGame => ["ПОКИНУТЬ ИГРУ?", "LEAVE THE GAME?"],
+            Self::Stay => ["ОСТАТЬСЯ", "STAY"],
+            Self::NoPause => ["ПАУЗЫ НЕТ", "NO PAUSE"],
+            Self::Spectating => ["ПРОСМОТР ИГРЫ", "SPECTATING"],
+            Self::WaitingForGame => ["ЖДЁМ НАЧАЛА ИГРЫ", "WAITING FOR A GAME"],
+            Self::KeySpace => ["ПРОБЕЛ", "SPACE"],
+            Self::KeyEnter => ["ВВОД", "ENTER"]
+        }
+    }
+}
+
+pub fn tr(msg: Msg) -> &'static str {
+    msg.texts()[language() as usize]
+}
+
+// Message with every {} replaced by the next argument
+pub fn tr_args(msg: Msg, args: &[&dyn Display]) -> String {
+    let mut parts = tr(msg).split("{}");
+    let mut text = parts.next().unwrap_or("").to_string();
+    for (idx, part) in parts.enumerate() {
+        if let Some(arg) = args.get(idx) {
+            text += &arg.to_string();
+        }
+        text += part;
+    }
+    text
+}
@@ -0,0 +1,136 @@
+use std::vec::Vec;
+use crossterm::{ExecutableCommand, QueueableCommand, terminal, cursor, style};
+use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use crossterm::event;
+use std::time::{Duration, Instant};
+use std::io::{stdout, Write};
+use std::sync::Mutex;
+use super::{Color, Key};
+
+// Last terminal size reported by a resize event which was not yet picked up
+static PENDING_RESIZE: Mutex<Option<(u16, u16)>> = Mutex::new(None);
+
+pub fn clr_scr() {
+    let mut stdout = stdout();
+    // Errors of a closed or broken terminal are ignored, as when the
+    // terminal is restored
+    let _ = stdout.execute(terminal::Clear(terminal::ClearType::All));
+}
+
+fn terminal_color(color: Color) -> style::Color {
+    match color {
+        Color::Red => style::Color::Red,
+        Color::Green => style::Color::Green,
+        Color::Yellow => style::Color::Yellow,
+        Color::Blue => style::Color::Blue,
+        Color::Magenta => style::Color::Magenta,
+        Color::Cyan => style::Color::Cyan,
+        Color::White => style::Color::White,
+        Color::Orange => style::Color::AnsiValue(208),
+        Color::Purple => style::Color::AnsiValue(93),
+        Color::Grey => style::Color::Grey
+    }
+}
+
+pub fn display(data: &[Vec<char>], colors: &[Vec<Option<Color>>]) {
+    let mut stdout = stdout();
+
+    // Every row is positioned explicitly, so a canvas as wide as the
+    // terminal does not wrap into the next line
+    for (y, (row, row_colors)) in data.iter().zip(colors).enumerate() {
+        let _ = stdout.queue(cursor::MoveTo(0, y as u16));
+        // Characters of one colour go out together
+        let mut x = 0;
+        while x < row.len() {
+            let color = row_colors[x];
+            let run = row_colors[x..].iter().take_while(|cell_color| **cell_color == color).count();
+            let line: String = row[x..x + run].iter().filter(|ch| **ch != super::WIDE_FILLER).collect();
+            match color {
+                Some(color) => {
+                    let _ = stdout.queue(style::SetForegroundColor(terminal_color(color)));
+                    let _ = stdout.write_all(line.as_bytes());
+                    let _ = stdout.queue(style::ResetColor);
+                }
+                None => {
+                    let _ = stdout.write_all(line.as_bytes());
+                }
+            }
+            x += run;
+        }
+    }
+    let _ = stdout.flush();
+
+    // Reset cursor position
+    let _ = stdout.execute(cursor::MoveTo(0, 0));
+}
+
+pub fn backend_init() {
+    let _ = enable_raw_mode();
+}
+
+pub fn backend_deinit() {
+    let _ = disable_raw_mode();
+    let mut stdout = stdout();
+    let _ = stdout.execute(terminal::Clear(terminal::ClearType::All));
+    let _ = stdout.execute(cursor::MoveTo(0, 0));
+    let _ = stdout.execute(cursor::Show);
+}
+
+pub fn backend_request_redraw() {
+    // Redraw goes the same way as a resize to the current terminal size
+    if let Ok((width, height)) = terminal::size() {
+        store_resize(width, height);
+    }
+}
+
+pub fn backend_screen_size() -> Option<(usize, usize)> {
+    terminal::size().ok().map(|(width, height)| (width as usize, height as usize))
+}
+
+pub fn backend_take_resize() -> Option<(usize, usize)> {
+    PENDING_RESIZE.lock().ok()?.take().map(|(width, height)| (width as usize, height as usize))
+}
+
+fn store_resize(width: u16, height: u16) {
+    if let Ok(mut pending) = PENDING_RESIZE.lock() {
+        *pending = Some((width, height));
+    }
+}
+
+pub fn backend_getch(timeout: Duration)->Option<Key> {
+    // Gets a single key press from the user,
+    // or None if nothing was pressed in time.
+    // Keys pressed after that one stay in the queue for the next call
+    let deadline = Instant::now() + timeout;
+    while let Ok(true) = event::poll(deadline.saturating_duration_since(Instant::now())) {
+        if let Ok(evt) = crossterm::event::read() {
+            if let crossterm::event::Event::Key(kevent) = evt {
+                if kevent.kind == KeyEventKind::Press {
+                    let key = match kevent.code {
+                        KeyCode::Char(ch) => {
+                            if kevent.modifiers.contains(KeyModifiers::CONTROL) {
+                                super::control_key(ch);
+                                continue;
+                            }
+                            Key::Char(ch)
+                        }
+                        KeyCode::Enter => Key::Enter,
+                        KeyCode::Esc => Key::Esc,
+                        KeyCode::Backspace => Key::Backspace,
+                        KeyCode::Tab => Key::Tab,
+                        KeyCode::Left => Key::Left,
+                        KeyCode::Right => Key::Right,
+                        KeyCode::Up => Key::Up,
+                        KeyCode::Down => Key::Down,
+                        _ => continue
+                    };
+                    return Some(key);
+                }
+            } else if let crossterm::event::Event::Resize(width, height) = evt {
+                store_resize(width, height);
+            }
+        }
+    }
+    None
+}
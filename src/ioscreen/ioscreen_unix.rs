@@ -0,0 +1,28 @@
+use signal_hook::consts::signal::{SIGINT, SIGTERM, SIGTSTP};
+use signal_hook::iterator::Signals;
+use signal_hook::low_level;
+use std::thread;
+
+pub fn install_signal_handlers() {
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGTSTP]) {
+        Ok(signals) => signals,
+        Err(_) => return
+    };
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                // The terminal belongs to the main thread, it suspends the
+                // game when it next waits for a key
+                SIGTSTP => super::request_suspend(),
+                _ => super::request_quit()
+            }
+        }
+    });
+}
+
+// Stops the process the same way it is stopped without a handler, returns
+// after SIGCONT
+pub fn stop() {
+    let _ = low_level::emulate_default_handler(SIGTSTP);
+}
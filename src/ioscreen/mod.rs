@@ -1,27 +1,253 @@
 use std::vec::Vec;
-#[cfg(target_os = "windows")]
-mod ioscreen_win;
-#[cfg(target_os = "windows")]
-use ioscreen_win::*;
+use std::panic;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use crate::i18n::{tr, Msg};
+// Crossterm backend, it drives Windows consoles and unix terminals alike
+#[cfg(any(target_os = "windows", unix))]
+mod ioscreen_term;
+#[cfg(any(target_os = "windows", unix))]
+use ioscreen_term::*;
+#[cfg(unix)]
+mod ioscreen_unix;
+
+// Set while the terminal is switched into the raw game mode
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
+// Set by Ctrl+C, SIGINT or SIGTERM, the game loop exits when it sees it
+static QUIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+// Set by Ctrl+Z or SIGTSTP, the game is suspended before the next key is
+// read
+#[cfg(unix)]
+static SUSPEND_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Keeps the terminal in the game mode for as long as it lives and restores
+// it on drop, on panic and on termination signals
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+    // Taking over the terminal is a side effect, not a default value
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        init();
+        install_panic_hook();
+        #[cfg(unix)]
+        ioscreen_unix::install_signal_handlers();
+        TerminalGuard
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        deinit();
+    }
+}
+
+// Second cell of a double width character, it is not printed itself
+const WIDE_FILLER: char = '\0';
 
 pub struct Canvas {
-    display_data: Vec<Vec<char>>
+    display_data: Vec<Vec<char>>,
+    // Colour of every cell, None is the default colour of the terminal
+    colors: Vec<Vec<Option<Color>>>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Orange,
+    Purple,
+    Grey
+}
+
+// Name used in configuration files and a one letter code for saved glasses
+const COLOR_NAMES: [(Color, &str, char); 10] = [
+    (Color::Red, "red", 'r'),
+    (Color::Green, "green", 'g'),
+    (Color::Yellow, "yellow", 'y'),
+    (Color::Blue, "blue", 'b'),
+    (Color::Magenta, "magenta", 'm'),
+    (Color::Cyan, "cyan", 'c'),
+    (Color::White, "white", 'w'),
+    (Color::Orange, "orange", 'o'),
+    (Color::Purple, "purple", 'p'),
+    (Color::Grey, "grey", 'e')
+];
+
+impl Color {
+    pub fn from_name(name: &str) -> Option<Color> {
+        COLOR_NAMES.iter().find(|(_, color_name, _)| *color_name == name).map(|(color, _, _)| *color)
+    }
+
+    pub fn code(&self) -> char {
+        COLOR_NAMES.iter().find(|(color, _, _)| color == self).map_or('#', |(_, _, code)| *code)
+    }
+
+    pub fn from_code(code: char) -> Option<Color> {
+        COLOR_NAMES.iter().find(|(_, _, color_code)| *color_code == code).map(|(color, _, _)| *color)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Esc,
+    Backspace,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down
+}
+
+// Keys with a translated title are looked up in the catalog instead
+const KEY_NAMES: [(Key, &str, &str); 9] = [
+    (Key::Char(' '), "space", ""),
+    (Key::Enter, "enter", ""),
+    (Key::Esc, "esc", "ESC"),
+    (Key::Backspace, "backspace", "BKSP"),
+    (Key::Tab, "tab", "TAB"),
+    (Key::Left, "left", "←"),
+    (Key::Right, "right", "→"),
+    (Key::Up, "up", "↑"),
+    (Key::Down, "down", "↓")
+];
+
+impl Key {
+    // Name used in configuration files
+    pub fn name(&self) -> String {
+        match KEY_NAMES.iter().find(|(key, _, _)| key == self) {
+            Some((_, name, _)) => name.to_string(),
+            None => match self {
+                Key::Char(ch) => ch.to_string(),
+                _ => String::new()
+            }
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Key> {
+        if let Some((key, _, _)) = KEY_NAMES.iter().find(|(_, key_name, _)| *key_name == name) {
+            return Some(*key);
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(Key::Char(ch)),
+            _ => None
+        }
+    }
+
+    // Name shown on the screen
+    pub fn title(&self) -> String {
+        match self {
+            Key::Char(' ') => return tr(Msg::KeySpace).to_string(),
+            Key::Enter => return tr(Msg::KeyEnter).to_string(),
+            _ => {}
+        }
+        match KEY_NAMES.iter().find(|(key, _, _)| key == self) {
+            Some((_, _, title)) => title.to_string(),
+            None => match self {
+                Key::Char(ch) => ch.to_uppercase().to_string(),
+                _ => String::new()
+            }
+        }
+    }
+}
+
+// Number of terminal columns the text takes, CJK characters take two
+pub fn text_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+// Text padded with spaces on the right up to the given width
+pub fn pad_right(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text_width(text))))
+}
+
+// Text padded with spaces on the left up to the given width
+pub fn pad_left(text: &str, width: usize) -> String {
+    format!("{}{}", " ".repeat(width.saturating_sub(text_width(text))), text)
 }
 
 pub fn clear_screen() {
     clr_scr();
 }
 
-pub fn getch() -> Option<char> {
-    backend_getch()
+// Waits up to timeout for a key press
+pub fn getch(timeout: Duration) -> Option<Key> {
+    #[cfg(unix)]
+    if SUSPEND_REQUESTED.swap(false, Ordering::SeqCst) {
+        suspend();
+    }
+    backend_getch(timeout)
+}
+
+pub fn screen_size() -> Option<(usize, usize)> {
+    backend_screen_size()
+}
+
+pub fn take_resize() -> Option<(usize, usize)> {
+    backend_take_resize()
+}
+
+pub fn quit_requested() -> bool {
+    QUIT_REQUESTED.load(Ordering::SeqCst)
+}
+
+fn request_quit() {
+    QUIT_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn request_suspend() {
+    SUSPEND_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+// The terminal is restored while the process is stopped and the screen is
+// drawn anew on resume
+#[cfg(unix)]
+fn suspend() {
+    deinit();
+    ioscreen_unix::stop();
+    init();
+    backend_request_redraw();
+}
+
+// Raw mode disables the terminal's own handling of Ctrl+C and Ctrl+Z
+fn control_key(ch: char) {
+    match ch {
+        'c' => request_quit(),
+        #[cfg(unix)]
+        'z' => request_suspend(),
+        _ => {}
+    }
+}
+
+fn init() {
+    backend_init();
+    TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
 }
 
-pub fn init() {
-    backend_init()
+fn deinit() {
+    if TERMINAL_ACTIVE.swap(false, Ordering::SeqCst) {
+        backend_deinit();
+    }
 }
 
-pub fn deinit() {
-    backend_deinit()
+fn install_panic_hook() {
+    // Terminal goes back to normal before the panic message is printed,
+    // otherwise the message is mangled by raw mode
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        deinit();
+        default_hook(info);
+    }));
 }
 
 impl Canvas {
@@ -29,22 +255,74 @@ impl Canvas {
         let display_data: Vec<Vec<char>> = (0..height).map(|_| {
             (0..width).map(|_| {' '}).collect()
         }).collect();
-        Canvas {display_data}
+        Canvas {colors: vec![vec![None; width]; height], display_data}
+    }
+
+    pub fn width(&self) -> usize {
+        self.display_data.first().map_or(0, |row| row.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.display_data.len()
     }
 
     pub fn set_char(&mut self, x: usize, y: usize, ch: char) {
-        self.display_data[y][x] = ch;
+        self.set_colored_char(x, y, ch, None);
+    }
+
+    pub fn set_colored_char(&mut self, x: usize, y: usize, ch: char, color: Option<Color>) {
+        // Anything outside of the canvas is clipped
+        let row = match self.display_data.get_mut(y) {
+            Some(row) if x < row.len() => row,
+            _ => return
+        };
+        // Drawing over a half of a double width character erases all of it,
+        // otherwise the rest of the row would shift
+        if row[x] == WIDE_FILLER && x > 0 {
+            row[x - 1] = ' ';
+        }
+        if row.get(x + 1) == Some(&WIDE_FILLER) {
+            row[x + 1] = ' ';
+        }
+        row[x] = ch;
+        self.colors[y][x] = color;
+    }
+
+    pub fn draw_text(&mut self, text: &str, x: usize, y: usize) {
+        if y >= self.height() {
+            return;
+        }
+        let mut char_x_pos = x;
+        for character in text.chars() {
+            let width = character.width().unwrap_or(0);
+            if width == 0 {
+                continue;
+            }
+            if width == 2 {
+                // A double width character cut by the canvas edge is not drawn
+                if char_x_pos + 1 >= self.width() {
+                    break;
+                }
+                self.set_char(char_x_pos + 1, y, ' ');
+                self.set_char(char_x_pos, y, character);
+                self.display_data[y][char_x_pos + 1] = WIDE_FILLER;
+            } else {
+                self.set_char(char_x_pos, y, character);
+            }
+            char_x_pos += width;
+        }
     }
 
     pub fn display(&self) {
-        display(&self.display_data);
+        display(&self.display_data, &self.colors);
     }
 
     pub fn clear(&mut self) {
         for row in &mut self.display_data {
-            for col in 0..row.len() {
-                row[col] = ' ';
-            }
+            row.fill(' ');
+        }
+        for row in &mut self.colors {
+            row.fill(None);
         }
     }
 }
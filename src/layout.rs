@@ -0,0 +1,108 @@
+// Placement of the glass and the sidebars on a canvas of a given size
+
+// Width of the score block on the left of the glass
+const STATS_WIDTH: usize = 22;
+// Width of the key help block on the right of the glass
+const HELP_WIDTH: usize = 22;
+// Empty columns between the glass and a sidebar
+const SIDEBAR_GAP: usize = 2;
+// Glass border takes "<!" on the left and "!>" on the right
+const BORDER_WIDTH: usize = 2;
+// Two rows below the glass: "=====" and "\/\/\/"
+const BOTTOM_HEIGHT: usize = 2;
+// Columns right of each of several glasses for the meter of rows coming to it
+const METER_WIDTH: usize = 3;
+// Empty columns between glasses side by side
+const BOARDS_GAP: usize = 4;
+// Line above each of several glasses for who plays there
+const TITLE_HEIGHT: usize = 1;
+
+pub struct Layout {
+    pub canvas_width: usize,
+    pub canvas_height: usize,
+    // Column of the leftmost glass cell and row of the topmost one
+    pub glass_left: usize,
+    pub glass_top: usize,
+    // Column of the right wall, just past the rightmost glass cell
+    pub glass_right: usize,
+    // Top left corners of the sidebars, None if they do not fit
+    pub stats: Option<(usize, usize)>,
+    pub help: Option<(usize, usize)>,
+    pub too_small: bool,
+}
+
+impl Layout {
+    pub fn new(canvas_width: usize, canvas_height: usize, glass_width: usize, glass_height: usize) -> Self {
+        let (min_width, min_height) = Self::min_size(glass_width, glass_height);
+        if canvas_width < min_width || canvas_height < min_height {
+            return Layout {
+                canvas_width,
+                canvas_height,
+                glass_left: 0,
+                glass_top: 0,
+                glass_right: 0,
+                stats: None,
+                help: None,
+                too_small: true
+            };
+        }
+
+        // Glass is centred, every glass cell is two characters wide
+        let outer_left = (canvas_width - min_width) / 2;
+        let outer_right = outer_left + min_width;
+        let glass_left = outer_left + BORDER_WIDTH;
+        let glass_top = (canvas_height - min_height) / 2;
+
+        let stats = if outer_left >= STATS_WIDTH + SIDEBAR_GAP {
+            Some((outer_left - STATS_WIDTH - SIDEBAR_GAP, glass_top))
+        } else {
+            None
+        };
+        let help = if canvas_width - outer_right >= HELP_WIDTH + SIDEBAR_GAP {
+            Some((outer_right + SIDEBAR_GAP, glass_top + 1))
+        } else {
+            None
+        };
+
+        let glass_right = glass_left + glass_width * 2;
+        Layout {canvas_width, canvas_height, glass_left, glass_top, glass_right, stats, help, too_small: false}
+    }
+
+    pub fn min_size(glass_width: usize, glass_height: usize) -> (usize, usize) {
+        (glass_width * 2 + BORDER_WIDTH * 2, glass_height + BOTTOM_HEIGHT)
+    }
+
+    // Glasses of the same size side by side, without sidebars. Each one has
+    // a title line above it and a meter on its right
+    pub fn side_by_side(canvas_width: usize, canvas_height: usize, glass_width: usize, glass_height: usize,
+                        count: usize) -> Vec<Self> {
+        let (min_width, min_height) = Self::side_by_side_min_size(glass_width, glass_height, count);
+        let too_small = canvas_width < min_width || canvas_height < min_height;
+        let (board_width, _) = Self::min_size(glass_width, glass_height);
+        let outer_left = canvas_width.saturating_sub(min_width) / 2;
+        let glass_top = if too_small {0} else {(canvas_height - min_height) / 2 + TITLE_HEIGHT};
+        (0..count).map(|idx| {
+            let glass_left = if too_small {0} else {outer_left + idx * (board_width + METER_WIDTH + BOARDS_GAP) + BORDER_WIDTH};
+            Layout {
+                canvas_width,
+                canvas_height,
+                glass_left,
+                glass_top,
+                glass_right: if too_small {0} else {glass_left + glass_width * 2},
+                stats: None,
+                help: None,
+                too_small
+            }
+        }).collect()
+    }
+
+    pub fn side_by_side_min_size(glass_width: usize, glass_height: usize, count: usize) -> (usize, usize) {
+        let (width, height) = Self::min_size(glass_width, glass_height);
+        ((width + METER_WIDTH) * count + BOARDS_GAP * count.saturating_sub(1), height + TITLE_HEIGHT)
+    }
+
+    // Column of the meter next to the glass
+    pub fn meter_x(&self) -> usize {
+        self.glass_right + BORDER_WIDTH + 1
+    }
+}
@@ -0,0 +1,25 @@
+pub mod ioscreen;
+pub mod game;
+pub mod board;
+pub mod field;
+pub mod garbage;
+pub mod piece;
+pub mod layout;
+pub mod gravity;
+pub mod score;
+pub mod mode;
+pub mod menu;
+pub mod settings;
+pub mod storage;
+pub mod random;
+pub mod app;
+pub mod highscores;
+pub mod i18n;
+pub mod replay;
+pub mod savegame;
+pub mod ai;
+pub mod placement;
+pub mod versus;
+pub mod net;
+pub mod netplay;
+pub mod spectate;
@@ -1,12 +1,110 @@
+use rustris::app::App;
+use rustris::highscores;
+use rustris::ioscreen;
+use rustris::mode::{self, GameMode};
+use rustris::net::{self, DEFAULT_PORT};
+use rustris::netplay::NetGame;
+use rustris::replay::{LoadError, Replay};
+use rustris::settings::Settings;
+use rustris::spectate::{Broadcast, Spectator};
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
 
-mod ioscreen;
-mod game;
-use game::Game;
+fn usage() -> ! {
+    let modes: Vec<&str> = mode::GAME_MODES.iter().map(|mode| mode.name()).collect();
+    eprintln!("usage: rustris [--mode {}] [--replay FILE] [--broadcast PORT]", modes.join("|"));
+    eprintln!("       rustris --host PORT | --join HOST[:PORT] [--name NAME] [--broadcast PORT]");
+    eprintln!("       rustris --watch PORT");
+    process::exit(2);
+}
+
+fn exit_with<T>(error: io::Error) -> T {
+    eprintln!("rustris: {}", error);
+    process::exit(1);
+}
 
 fn main()
 {
-    ioscreen::init();
-    let mut game_instance = Game::new();
-    game_instance.game_loop();
-    ioscreen::deinit();
-}
\ No newline at end of file
+    let mut game_mode = None;
+    let mut replay = None;
+    // Port to host a network game on, or the address of the host to join
+    let mut host_port = None;
+    let mut join_address = None;
+    let mut name = highscores::default_name();
+    // Local port to send the games to spectators on, or to watch a game on
+    let mut broadcast_port = None;
+    let mut watch_port = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                game_mode = Some(args.next().and_then(|name| GameMode::from_name(&name)).unwrap_or_else(|| usage()));
+            }
+            "--replay" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                match Replay::load(Path::new(&path)) {
+                    Ok(loaded) => replay = Some(loaded),
+                    Err(LoadError::Unreadable) => {
+                        eprintln!("rustris: can not read replay {}", path);
+                        process::exit(1);
+                    }
+                    Err(LoadError::PiecesChanged) => {
+                        eprintln!("rustris: the pieces of replay {} are gone or changed", path);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--host" => host_port = Some(args.next().and_then(|port| port.parse::<u16>().ok()).unwrap_or_else(|| usage())),
+            "--join" => {
+                let address = args.next().unwrap_or_else(|| usage());
+                join_address = Some(if address.contains(':') {address} else {format!("{}:{}", address, DEFAULT_PORT)});
+            }
+            "--name" => name = args.next().unwrap_or_else(|| usage()),
+            "--broadcast" => broadcast_port = Some(args.next().and_then(|port| port.parse::<u16>().ok()).unwrap_or_else(|| usage())),
+            "--watch" => watch_port = Some(args.next().and_then(|port| port.parse::<u16>().ok()).unwrap_or_else(|| usage())),
+            _ => usage()
+        }
+    }
+
+    // The network is set up before the terminal is taken over, so errors
+    // can be printed
+    let settings = Settings::load();
+    let name = net::clean_name(&name);
+    // A spectator only watches, there is nothing else to play
+    let playing = broadcast_port.is_some() || host_port.is_some() || join_address.is_some()
+        || game_mode.is_some() || replay.is_some();
+    let spectator = match (watch_port, playing) {
+        (Some(_), true) => usage(),
+        (Some(port), false) => Some(Spectator::watch(settings.clone(), port).unwrap_or_else(exit_with)),
+        (None, _) => None
+    };
+    let net_game = match (host_port, &join_address) {
+        (Some(_), Some(_)) => usage(),
+        (Some(port), None) => Some(NetGame::host(settings.clone(), port, &name)),
+        (None, Some(address)) => Some(NetGame::join(settings.clone(), address, &name)),
+        (None, None) => None
+    };
+    let net_game = net_game.map(|net_game| net_game.unwrap_or_else(exit_with));
+    let broadcast = broadcast_port.map(|port| Broadcast::new(port, &name).unwrap_or_else(exit_with));
+
+    let _terminal = ioscreen::TerminalGuard::new();
+    settings.apply_language();
+    if let Some(mut spectator) = spectator {
+        spectator.game_loop();
+        return;
+    }
+    if let Some(mut net_game) = net_game {
+        net_game.set_broadcast(broadcast);
+        net_game.game_loop();
+        return;
+    }
+    let mut app = match (replay, game_mode) {
+        (Some(replay), _) => App::new_replay(settings, replay),
+        (None, Some(mode)) => App::new_game(settings, mode),
+        (None, None) => App::new(settings)
+    };
+    app.set_broadcast(broadcast);
+    app.run();
+}
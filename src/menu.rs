@@ -0,0 +1,103 @@
+use crate::ioscreen::{self, Canvas, Key};
+use crate::settings::{Action, Settings};
+
+pub enum MenuEvent {
+    None,
+    Select(usize),
+    // Left (-1) or right (+1) pressed on an item
+    Change(usize, isize),
+    Back,
+    // The quit key, menus with a Quit item move to it and choose it when it
+    // is pressed there
+    Quit
+}
+
+pub struct Menu {
+    title: String,
+    items: Vec<String>,
+    pub selected: usize
+}
+
+impl Menu {
+    pub fn new(title: &str, items: Vec<String>) -> Self {
+        Menu {title: title.to_string(), items, selected: 0}
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+
+    pub fn set_items(&mut self, items: Vec<String>) {
+        self.items = items;
+        self.selected = self.selected.min(self.items.len().saturating_sub(1));
+    }
+
+    pub fn handle_key(&mut self, key: Key, settings: &Settings) -> MenuEvent {
+        // Arrows, Enter and Esc always work, the game bindings work too
+        let action = match key {
+            Key::Up => Some(Action::Rotate),
+            Key::Down => Some(Action::SpeedUp),
+            Key::Left => Some(Action::Left),
+            Key::Right => Some(Action::Right),
+            Key::Enter | Key::Char(' ') => return MenuEvent::Select(self.selected),
+            Key::Esc => return MenuEvent::Back,
+            _ => settings.action(key)
+        };
+
+        match action {
+            Some(Action::Rotate) => {
+                self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+            }
+            Some(Action::SpeedUp) => {
+                self.selected = (self.selected + 1) % self.items.len();
+            }
+            Some(Action::Left) => return MenuEvent::Change(self.selected, -1),
+            Some(Action::Right) => return MenuEvent::Change(self.selected, 1),
+            Some(Action::Drop) => return MenuEvent::Select(self.selected),
+            Some(Action::Pause) => return MenuEvent::Back,
+            Some(Action::Quit) => return MenuEvent::Quit,
+            None => {}
+        }
+        MenuEvent::None
+    }
+
+    // Draws the menu in a frame centred at the given point
+    pub fn draw(&self, canvas: &mut Canvas, center_x: usize, center_y: usize) {
+        let mut lines = vec![self.title.clone(), String::new()];
+        for (idx, item) in self.items.iter().enumerate() {
+            if idx == self.selected {
+                lines.push(format!("> {} <", item));
+            } else {
+                lines.push(format!("  {}  ", item));
+            }
+        }
+        draw_panel(canvas, &lines, center_x, center_y);
+    }
+}
+
+// Draws centred lines of text in a frame centred at the given point
+pub fn draw_panel(canvas: &mut Canvas, lines: &[String], center_x: usize, center_y: usize) {
+    let text_width = lines.iter().map(|line| ioscreen::text_width(line)).max().unwrap_or(0);
+    let width = text_width + 4;
+    let height = lines.len() + 2;
+    let left = center_x.saturating_sub(width / 2);
+    let top = center_y.saturating_sub(height / 2);
+
+    for y in top..top + height {
+        for x in left..left + width {
+            let ch = if y == top || y == top + height - 1 {
+                '='
+            } else if x == left || x == left + width - 1 {
+                '!'
+            } else {
+                ' '
+            };
+            canvas.set_char(x, y, ch);
+        }
+    }
+
+    for (idx, line) in lines.iter().enumerate() {
+        let line_x = left + (width - ioscreen::text_width(line)) / 2;
+        canvas.draw_text(line, line_x, top + 1 + idx);
+    }
+}
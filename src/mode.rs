@@ -0,0 +1,157 @@
+use crate::game::GLASS_WIDTH_RANGE;
+use crate::gravity::GravityCurve;
+use crate::i18n::{tr, Msg};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GameMode {
+    // Guideline speed, won at 150 lines
+    Marathon,
+    // Guideline speed with no goal
+    Endless,
+    // 40 lines as fast as possible
+    Sprint,
+    // As many points as possible in two minutes
+    Ultra,
+    // Endless game with the NES speed
+    Classic,
+    // TGM speed, level goes up with every shape and reaches 20G
+    Master,
+    // Endless game with the 18 one sided pentominoes in a wider glass
+    Pentomino,
+    // Endless game with tetrominoes and now and then a smaller or bigger piece
+    Extras,
+    // 18 garbage rows cleared as fast as possible, the glass keeps 10 of them
+    Cheese,
+    // Endless game in a glass of garbage which keeps rising
+    Dig
+}
+
+pub const GAME_MODES: [GameMode; 10] = [
+    GameMode::Marathon, GameMode::Endless, GameMode::Sprint, GameMode::Ultra, GameMode::Classic, GameMode::Master,
+    GameMode::Pentomino, GameMode::Extras, GameMode::Cheese, GameMode::Dig
+];
+
+// Columns the pentomino glass has over the glass size of the settings
+const PENTOMINO_EXTRA_WIDTH: usize = 2;
+// Garbage rows the dig modes start with, the cheese race keeps the glass
+// topped up to as many
+const CHEESE_ROWS: u32 = 10;
+// Seconds between two garbage rows rising in the dig mode
+const DIG_RISE_SECS: u64 = 5;
+
+// What ends a game besides the glass overflowing
+#[derive(Clone, Copy, PartialEq)]
+pub enum Goal {
+    Endless,
+    // Won once this many lines are cleared
+    Lines(u32),
+    // Over once this many seconds are played
+    TimeLimit(u64),
+    // Won once this many garbage rows are cleared
+    GarbageLines(u32)
+}
+
+// What puts a game higher in the high score table
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ranking {
+    Points,
+    // Shorter is better, only games which reached the goal count
+    Time
+}
+
+impl GameMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Marathon => "marathon",
+            Self::Endless => "endless",
+            Self::Sprint => "sprint",
+            Self::Ultra => "ultra",
+            Self::Classic => "classic",
+            Self::Master => "master",
+            Self::Pentomino => "pentomino",
+            Self::Extras => "extras",
+            Self::Cheese => "cheese",
+            Self::Dig => "dig"
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        tr(match self {
+            Self::Marathon => Msg::Marathon,
+            Self::Endless => Msg::Endless,
+            Self::Sprint => Msg::Sprint,
+            Self::Ultra => Msg::Ultra,
+            Self::Classic => Msg::Classic,
+            Self::Master => Msg::Master,
+            Self::Pentomino => Msg::Pentomino,
+            Self::Extras => Msg::Extras,
+            Self::Cheese => Msg::Cheese,
+            Self::Dig => Msg::Dig
+        })
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        GAME_MODES.iter().find(|mode| mode.name() == name).copied()
+    }
+
+    pub fn gravity_curve(&self) -> GravityCurve {
+        match self {
+            Self::Marathon | Self::Endless | Self::Sprint | Self::Ultra | Self::Pentomino | Self::Extras
+                | Self::Cheese | Self::Dig => GravityCurve::Guideline,
+            Self::Classic => GravityCurve::Nes,
+            Self::Master => GravityCurve::Tgm
+        }
+    }
+
+    pub fn goal(&self) -> Goal {
+        match self {
+            Self::Marathon => Goal::Lines(150),
+            Self::Sprint => Goal::Lines(40),
+            Self::Ultra => Goal::TimeLimit(120),
+            Self::Cheese => Goal::GarbageLines(18),
+            Self::Endless | Self::Classic | Self::Master | Self::Pentomino | Self::Extras | Self::Dig => Goal::Endless
+        }
+    }
+
+    pub fn ranking(&self) -> Ranking {
+        match self {
+            Self::Sprint | Self::Cheese => Ranking::Time,
+            _ => Ranking::Points
+        }
+    }
+
+    // Piece set the mode is played with, None takes the one of the settings
+    pub fn piece_set(&self) -> Option<&'static str> {
+        match self {
+            Self::Pentomino => Some("pentomino"),
+            Self::Extras => Some("extras"),
+            _ => None
+        }
+    }
+
+    // Garbage rows the glass starts with, never more than half of it
+    pub fn cheese_rows(&self, glass_height: usize) -> u32 {
+        match self {
+            Self::Cheese | Self::Dig => CHEESE_ROWS.min(glass_height as u32 / 2),
+            _ => 0
+        }
+    }
+
+    // Seconds between two garbage rows rising from the bottom, None when
+    // garbage does not rise
+    pub fn garbage_rise_secs(&self) -> Option<u64> {
+        match self {
+            Self::Dig => Some(DIG_RISE_SECS),
+            _ => None
+        }
+    }
+
+    // Glass of the mode for the size chosen in the settings
+    pub fn glass_size(&self, size: (usize, usize)) -> (usize, usize) {
+        let (width, height) = size;
+        match self {
+            Self::Pentomino => ((width + PENTOMINO_EXTRA_WIDTH).min(*GLASS_WIDTH_RANGE.end()), height),
+            _ => (width, height)
+        }
+    }
+}
@@ -0,0 +1,355 @@
+// Protocol of network games. One instance hosts, the others join it over
+// TCP and every message goes through the host, which passes the game
+// messages on to everybody else. Messages are lines of text, words are
+// separated by spaces:
+//
+//   HELLO version name          first line of a joining player
+//   WELCOME id                  the host takes the player in, the host is 0
+//   REFUSE reason               the host does not, and closes the connection
+//   LOBBY id,name,ready ...     everybody waiting for the next game
+//   READY                       the player wants to start
+//   START seed width height set pieces id ...
+//                               everybody was ready: seed of the pieces,
+//                               glass size, piece set and its fingerprint,
+//                               and the players
+//   GARBAGE from to rows        rows cleared by one player for another one
+//   SNAPSHOT id lines incoming row/row/...
+//                               glass of a player, as in Field::rows
+//   LOST id                     the glass of the player overflowed
+//   GONE id                     the player left or the connection broke
+//
+// Spectators connect to a game broadcasting its glasses and only listen,
+// see spectate.rs:
+//
+//   WATCH version               first line of a spectator
+//   VIEW width height id,name,out ...
+//                               glass size and the glasses shown, glasses
+//                               themselves come as snapshots
+//   STATE state                 idle, playing, paused or over
+//
+// A host refuses players of another protocol version, so the version goes
+// up with every change of the messages
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+pub const PROTOCOL_VERSION: u32 = 3;
+pub const DEFAULT_PORT: u16 = 7878;
+// Names are single words of the protocol
+const MAX_NAME_LENGTH: usize = 12;
+// The longest line is a snapshot of the largest glass, longer ones close
+// the connection
+const MAX_LINE_LENGTH: usize = 4096;
+// Lines waiting to be written, a peer that lets more pile up is not reading
+// and the connection is closed
+const MAX_QUEUED_LINES: usize = 256;
+// A peer which takes nothing for this long is gone
+const WRITE_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Clone, PartialEq)]
+pub enum Message {
+    Hello {version: u32, name: String},
+    Welcome {id: usize},
+    Refuse {reason: String},
+    Lobby {players: Vec<(usize, String, bool)>},
+    Ready,
+    Start {seed: u64, glass_size: (usize, usize), piece_set: String, pieces: u64, players: Vec<usize>},
+    Garbage {from: usize, to: usize, rows: u32},
+    Snapshot {id: usize, lines: u32, incoming: u32, rows: Vec<String>},
+    Lost {id: usize},
+    Gone {id: usize},
+    Watch {version: u32},
+    View {glass_size: (usize, usize), players: Vec<(usize, String, bool)>},
+    State {state: String}
+}
+
+// Spaces, commas and slashes separate things in messages, so names go
+// without them
+pub fn clean_name(name: &str) -> String {
+    let name: String = name.chars()
+        .filter(|ch| ch.is_alphanumeric() || *ch == '-' || *ch == '_')
+        .take(MAX_NAME_LENGTH)
+        .collect();
+    if name.is_empty() {"?".to_string()} else {name}
+}
+
+// Player as id,name,flag in LOBBY and VIEW
+fn parse_player(word: &str) -> Option<(usize, String, bool)> {
+    let mut parts = word.split(',');
+    let id = parts.next()?.parse().ok()?;
+    let name = clean_name(parts.next()?);
+    let flag = parts.next()? == "1";
+    Some((id, name, flag))
+}
+
+// Next word as a number, None for anything out of the range of the type
+fn number<T: FromStr>(words: &mut SplitWhitespace) -> Option<T> {
+    words.next()?.parse().ok()
+}
+
+impl Message {
+    pub fn to_line(&self) -> String {
+        match self {
+            Self::Hello {version, name} => format!("HELLO {} {}", version, name),
+            Self::Welcome {id} => format!("WELCOME {}", id),
+            Self::Refuse {reason} => format!("REFUSE {}", reason),
+            Self::Lobby {players} => {
+                let players: Vec<String> = players.iter()
+                    .map(|(id, name, ready)| format!("{},{},{}", id, name, if *ready {1} else {0}))
+                    .collect();
+                format!("LOBBY {}", players.join(" ")).trim_end().to_string()
+            }
+            Self::Ready => "READY".to_string(),
+            Self::Start {seed, glass_size, piece_set, pieces, players} => {
+                let players: Vec<String> = players.iter().map(|id| id.to_string()).collect();
+                format!("START {:016x} {} {} {} {:016x} {}", seed, glass_size.0, glass_size.1, piece_set, pieces, players.join(" "))
+            }
+            Self::Garbage {from, to, rows} => format!("GARBAGE {} {} {}", from, to, rows),
+            Self::Snapshot {id, lines, incoming, rows} => {
+                format!("SNAPSHOT {} {} {} {}", id, lines, incoming, rows.join("/")).trim_end().to_string()
+            }
+            Self::Lost {id} => format!("LOST {}", id),
+            Self::Gone {id} => format!("GONE {}", id),
+            Self::Watch {version} => format!("WATCH {}", version),
+            Self::View {glass_size, players} => {
+                let players: Vec<String> = players.iter()
+                    .map(|(id, name, out)| format!("{},{},{}", id, name, if *out {1} else {0}))
+                    .collect();
+                format!("VIEW {} {} {}", glass_size.0, glass_size.1, players.join(" ")).trim_end().to_string()
+            }
+            Self::State {state} => format!("STATE {}", state)
+        }
+    }
+
+    // None for anything this version does not know
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let kind = words.next()?;
+        let words = &mut words;
+        let message = match kind {
+            "HELLO" => Self::Hello {version: number(words)?, name: clean_name(words.next()?)},
+            "WELCOME" => Self::Welcome {id: number(words)?},
+            "REFUSE" => Self::Refuse {reason: words.collect::<Vec<&str>>().join(" ")},
+            "LOBBY" => Self::Lobby {players: words.map(parse_player).collect::<Option<Vec<_>>>()?},
+            "READY" => Self::Ready,
+            "START" => {
+                let seed = u64::from_str_radix(words.next()?, 16).ok()?;
+                let glass_size = (number(words)?, number(words)?);
+                let piece_set = words.next()?.to_string();
+                let pieces = u64::from_str_radix(words.next()?, 16).ok()?;
+                let players = words.map(|word| word.parse().ok()).collect::<Option<Vec<usize>>>()?;
+                Self::Start {seed, glass_size, piece_set, pieces, players}
+            }
+            "GARBAGE" => Self::Garbage {from: number(words)?, to: number(words)?, rows: number(words)?},
+            "SNAPSHOT" => {
+                let (id, lines, incoming) = (number(words)?, number(words)?, number(words)?);
+                // No rows before the first snapshot of a player came
+                let rows = words.next().map_or(Vec::new(), |rows| rows.split('/').map(|row| row.to_string()).collect());
+                Self::Snapshot {id, lines, incoming, rows}
+            }
+            "LOST" => Self::Lost {id: number(words)?},
+            "GONE" => Self::Gone {id: number(words)?},
+            "WATCH" => Self::Watch {version: number(words)?},
+            "VIEW" => {
+                let glass_size = (number(words)?, number(words)?);
+                Self::View {glass_size, players: words.map(parse_player).collect::<Option<Vec<_>>>()?}
+            }
+            "STATE" => Self::State {state: words.next()?.to_string()},
+            _ => return None
+        };
+        Some(message)
+    }
+}
+
+// Connection to another instance. Lines are read and written on threads of
+// their own, so the game loop never waits for the network
+pub struct Connection {
+    stream: TcpStream,
+    lines: SyncSender<String>,
+    messages: Receiver<Message>,
+    closed: bool
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let (sender, messages) = mpsc::channel();
+        // The thread ends with the connection or with a message this
+        // version does not understand, which closes the connection too
+        thread::spawn(move || {
+            let mut line = String::new();
+            loop {
+                line.clear();
+                let read = (&mut reader).take(MAX_LINE_LENGTH as u64 + 1).read_line(&mut line);
+                // Nothing read is the end of the connection, no line end
+                // within the limit is a line too long
+                if !matches!(read, Ok(length) if length > 0) || !line.ends_with('\n') {
+                    break;
+                }
+                let message = match Message::parse(&line) {
+                    Some(message) => message,
+                    None => break
+                };
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // The thread writes what is queued until the connection is dropped
+        // or a write fails, then shuts the connection down, which ends the
+        // reading thread as well
+        let mut writer = stream.try_clone()?;
+        writer.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS)))?;
+        let (lines, queued) = mpsc::sync_channel::<String>(MAX_QUEUED_LINES);
+        thread::spawn(move || {
+            for line in queued {
+                if writer.write_all(line.as_bytes()).is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+        Ok(Connection {stream, lines, messages, closed: false})
+    }
+
+    pub fn send(&mut self, message: &Message) {
+        if self.closed {
+            return;
+        }
+        match self.lines.try_send(message.to_line() + "\n") {
+            Ok(()) => (),
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => self.close()
+        }
+    }
+
+    // Messages which came since the last call
+    pub fn receive(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => messages.push(message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.close();
+                    break;
+                }
+            }
+        }
+        messages
+    }
+
+    // True once the other side is gone, the messages it sent before are
+    // received first
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn close(&mut self) {
+        self.closed = true;
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::time::Instant;
+
+    #[test]
+    fn messages_come_back_from_their_lines() {
+        let messages = [
+            Message::Hello {version: PROTOCOL_VERSION, name: "ann".to_string()},
+            Message::Welcome {id: 3},
+            Message::Refuse {reason: "full".to_string()},
+            Message::Lobby {players: vec![(0, "ann".to_string(), true), (1, "bob".to_string(), false)]},
+            Message::Lobby {players: Vec::new()},
+            Message::Ready,
+            Message::Start {seed: u64::MAX, glass_size: (10, 20), piece_set: "tetromino".to_string(), pieces: 0x1f, players: vec![0, 2]},
+            Message::Garbage {from: 1, to: 0, rows: 4},
+            Message::Snapshot {id: 1, lines: 12, incoming: 3, rows: vec!["..r".to_string(), "###".to_string()]},
+            Message::Snapshot {id: 2, lines: 0, incoming: 0, rows: Vec::new()},
+            Message::Lost {id: 2},
+            Message::Gone {id: 1},
+            Message::Watch {version: PROTOCOL_VERSION},
+            Message::View {glass_size: (12, 24), players: vec![(0, "ann".to_string(), false), (1, "bob".to_string(), true)]},
+            Message::State {state: "paused".to_string()}
+        ];
+        for message in messages {
+            let line = message.to_line();
+            assert!(Message::parse(&line) == Some(message), "{}", line);
+        }
+    }
+
+    #[test]
+    fn malformed_lines_are_not_messages() {
+        let lines = [
+            "",
+            "HELLO",
+            "HELLO two ann",
+            "HELLO 4294967296 ann",
+            "WELCOME -1",
+            "LOBBY 0,ann",
+            "LOBBY x,ann,1",
+            "START 1 10 20 tetromino",
+            "START 1 10 20 tetromino 1f 0 one",
+            "START seed 10 20 tetromino 1f 0",
+            "START 1 10 20 tetromino pieces 0",
+            "GARBAGE 1 0 4294967296",
+            "SNAPSHOT 1 12",
+            "SNAPSHOT 1 -12 0",
+            "VIEW 10",
+            "STATE",
+            "HOWDY 1"
+        ];
+        for line in lines {
+            assert!(Message::parse(line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn names_are_single_words() {
+        assert_eq!(clean_name("ann lee,1/2"), "annlee12");
+        assert_eq!(clean_name(" ,/"), "?");
+        assert_eq!(clean_name(&"a".repeat(MAX_NAME_LENGTH * 2)).len(), MAX_NAME_LENGTH);
+    }
+
+    #[test]
+    fn lines_too_long_close_the_connection() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut other_side = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut connection = Connection::new(listener.accept().unwrap().0).unwrap();
+        other_side.write_all(b"READY\n").unwrap();
+        other_side.write_all(format!("REFUSE {}\n", "x".repeat(MAX_LINE_LENGTH)).as_bytes()).unwrap();
+        other_side.write_all(b"READY\n").unwrap();
+
+        let mut received = Vec::new();
+        let start = Instant::now();
+        while !connection.is_closed() && start.elapsed() < Duration::from_secs(5) {
+            received.extend(connection.receive());
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(connection.is_closed());
+        assert!(received == [Message::Ready]);
+    }
+
+    #[test]
+    fn peers_not_reading_are_closed_without_waiting() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let _other_side = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut connection = Connection::new(listener.accept().unwrap().0).unwrap();
+        let snapshot = Message::Snapshot {id: 1, lines: 0, incoming: 0, rows: vec!["#".repeat(200); 20]};
+
+        // The socket buffers fill up, then the queue does
+        let start = Instant::now();
+        while !connection.is_closed() && start.elapsed() < Duration::from_secs(5) {
+            connection.send(&snapshot);
+        }
+        assert!(connection.is_closed());
+    }
+}
@@ -0,0 +1,982 @@
+// Versus games over the network, see net.rs for the protocol. Every
+// instance plays its own glass and shows the others from their snapshots,
+// garbage goes to the next player still in the game
+use crate::field::{self, Field};
+use crate::game::{GAME_CANVAS_HEIGHT, GAME_CANVAS_WIDTH, GLASS_HEIGHT_RANGE, GLASS_WIDTH_RANGE, LOGIC_RATE_HZ};
+use crate::garbage::Garbage;
+use crate::gravity::GravityCurve;
+use crate::i18n::{tr, tr_args, Msg};
+use crate::ioscreen::{self, pad_left, pad_right, text_width, Color, Key};
+use crate::layout::Layout;
+use crate::menu::{self, Menu, MenuEvent};
+use crate::net::{Connection, Message, PROTOCOL_VERSION};
+use crate::piece::PieceSet;
+use crate::random;
+use crate::settings::{self, Action, Settings};
+use crate::spectate::{Board, Broadcast, ViewState};
+use crate::storage;
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+const MAX_PLAYERS: usize = 4;
+const GRAVITY_CURVE: GravityCurve = GravityCurve::Guideline;
+// Logic frames between two snapshots of a glass which keeps changing
+const SNAPSHOT_FRAMES: u64 = 6;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+// Connections which do not say hello in time are not this game talking
+const HELLO_TIMEOUT_SECS: u64 = 5;
+const METER_COLOR: Color = Color::Red;
+const MAX_CATCH_UP_FRAMES: u32 = 10;
+// Id of the hosting player
+const HOST_ID: usize = 0;
+const LEAVE_ITEMS: [Msg; 2] = [Msg::Stay, Msg::Quit];
+const LEAVE_QUIT: usize = 1;
+
+enum Role {
+    // Joining players wait in the list until they say hello, the ones taken
+    // in are kept with their peers
+    Host {listener: TcpListener, port: u16, joining: Vec<(Connection, Instant)>, next_id: usize},
+    Client {host: Connection}
+}
+
+struct Peer {
+    id: usize,
+    name: String,
+    ready: bool,
+    // Connection of a joined player on the host, None for everybody else
+    connection: Option<Connection>,
+    // Out of the game being played, and gone from the game altogether
+    lost: bool,
+    gone: bool,
+    // Glass from the latest snapshot
+    lines: u32,
+    incoming: u32,
+    rows: Vec<String>
+}
+
+impl Peer {
+    fn new(id: usize, name: String, connection: Option<Connection>) -> Self {
+        Peer {id, name, ready: false, connection, lost: false, gone: false, lines: 0, incoming: 0, rows: Vec::new()}
+    }
+}
+
+// Game being played, or the last one
+struct Match {
+    field: Field,
+    garbage: Garbage,
+    // Moves of the shape waiting for the next logic frame
+    pending_actions: Vec<Action>,
+    // Ids of everybody in the game
+    players: Vec<usize>,
+    seed: u64,
+    frames: u64,
+    // Garbage rows sent to the others
+    sent: u32,
+    // Last snapshot sent, another one goes out when the glass changes
+    snapshot: Option<Message>
+}
+
+enum NetState {
+    // Waiting for the host to take the player in
+    Joining,
+    Lobby,
+    Playing,
+    // Id of the winner, None when nobody is left
+    Over(Option<usize>),
+    // The game can not go on, the text says why
+    Closed(String)
+}
+
+pub struct NetGame {
+    state: NetState,
+    settings: Settings,
+    role: Role,
+    // Own id, known once the host took the player in
+    id: usize,
+    // Everybody in the lobby including this player, in the order of ids
+    peers: Vec<Peer>,
+    game: Option<Match>,
+    screen_canvas: ioscreen::Canvas,
+    layouts: Vec<Layout>,
+    // Spectators watching the glasses on this screen
+    broadcast: Option<Broadcast>,
+    // Asks before leaving, the game goes on meanwhile as the others do not
+    // wait
+    leaving: Option<Menu>
+}
+
+impl NetGame {
+    fn new(settings: Settings, role: Role) -> Self {
+        let (width, height) = ioscreen::screen_size().unwrap_or((GAME_CANVAS_WIDTH, GAME_CANVAS_HEIGHT));
+        NetGame {
+            state: NetState::Joining,
+            settings,
+            role,
+            id: HOST_ID,
+            peers: Vec::new(),
+            game: None,
+            screen_canvas: ioscreen::Canvas::new(width, height),
+            layouts: Vec::new(),
+            broadcast: None,
+            leaving: None
+        }
+    }
+
+    // Waits for players on the port, on every interface
+    pub fn host(settings: Settings, port: u16, name: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+        let mut game = Self::new(settings, Role::Host {listener, port, joining: Vec::new(), next_id: HOST_ID + 1});
+        game.peers.push(Peer::new(HOST_ID, name.to_string(), None));
+        game.state = NetState::Lobby;
+        Ok(game)
+    }
+
+    // Connects to a host given as host:port
+    pub fn join(settings: Settings, address: &str, name: &str) -> io::Result<Self> {
+        let address = address.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no address"))?;
+        let stream = TcpStream::connect_timeout(&address, Duration::from_secs(CONNECT_TIMEOUT_SECS))?;
+        let mut host = Connection::new(stream)?;
+        host.send(&Message::Hello {version: PROTOCOL_VERSION, name: name.to_string()});
+        Ok(Self::new(settings, Role::Client {host}))
+    }
+
+    fn is_host(&self) -> bool {
+        matches!(self.role, Role::Host {..})
+    }
+
+    fn peer_mut(&mut self, id: usize) -> Option<&mut Peer> {
+        self.peers.iter_mut().find(|peer| peer.id == id)
+    }
+
+    fn peer(&self, id: usize) -> Option<&Peer> {
+        self.peers.iter().find(|peer| peer.id == id)
+    }
+
+    // Host only: to every joined player but one
+    fn broadcast(&mut self, message: &Message, except: Option<usize>) {
+        for peer in &mut self.peers {
+            if let (Some(connection), false) = (&mut peer.connection, Some(peer.id) == except) {
+                connection.send(message);
+            }
+        }
+    }
+
+    // Own game messages go to everybody, through the host
+    fn send_game(&mut self, message: Message) {
+        match &mut self.role {
+            Role::Host {..} => self.broadcast(&message, None),
+            Role::Client {host} => host.send(&message)
+        }
+    }
+
+    fn broadcast_lobby(&mut self) {
+        let players = self.peers.iter()
+            .filter(|peer| !peer.gone)
+            .map(|peer| (peer.id, peer.name.clone(), peer.ready))
+            .collect();
+        self.broadcast(&Message::Lobby {players}, None);
+    }
+
+    fn poll_network(&mut self) {
+        if self.is_host() {
+            self.poll_joining();
+            self.poll_peers();
+        } else {
+            self.poll_host();
+        }
+    }
+
+    // New players are taken in between games only
+    fn poll_joining(&mut self) {
+        let mut hellos = Vec::new();
+        if let Role::Host {listener, joining, ..} = &mut self.role {
+            while let Ok((stream, _)) = listener.accept() {
+                let connection = stream.set_nonblocking(false).and_then(|_| Connection::new(stream));
+                if let Ok(connection) = connection {
+                    joining.push((connection, Instant::now()));
+                }
+            }
+            let timeout = Duration::from_secs(HELLO_TIMEOUT_SECS);
+            for (mut connection, connected) in std::mem::take(joining) {
+                match connection.receive().into_iter().next() {
+                    Some(Message::Hello {version, name}) => hellos.push((connection, version, name)),
+                    None if !connection.is_closed() && connected.elapsed() < timeout => joining.push((connection, connected)),
+                    // Anything else first is not this game talking
+                    _ => {}
+                }
+            }
+        }
+
+        for (mut connection, version, name) in hellos {
+            let refusal = if version != PROTOCOL_VERSION {
+                Some("version")
+            } else if self.peers.len() >= MAX_PLAYERS {
+                Some("full")
+            } else if matches!(self.state, NetState::Playing) {
+                Some("busy")
+            } else {
+                None
+            };
+            if let Some(reason) = refusal {
+                connection.send(&Message::Refuse {reason: reason.to_string()});
+                continue;
+            }
+            if let Role::Host {next_id, ..} = &mut self.role {
+                let id = *next_id;
+                *next_id += 1;
+                connection.send(&Message::Welcome {id});
+                self.peers.push(Peer::new(id, name, Some(connection)));
+                self.broadcast_lobby();
+            }
+        }
+    }
+
+    fn poll_peers(&mut self) {
+        let mut received = Vec::new();
+        let mut closed = Vec::new();
+        for peer in &mut self.peers {
+            if let Some(connection) = &mut peer.connection {
+                received.extend(connection.receive().into_iter().map(|message| (peer.id, message)));
+                if connection.is_closed() {
+                    closed.push(peer.id);
+                }
+            }
+        }
+
+        for (from, message) in received {
+            match message {
+                Message::Ready if !matches!(self.state, NetState::Playing) => {
+                    if let Some(peer) = self.peer_mut(from) {
+                        peer.ready = true;
+                    }
+                    self.broadcast_lobby();
+                    self.try_start();
+                }
+                // Players speak for themselves only
+                Message::Garbage {from: sender, ..} | Message::Snapshot {id: sender, ..} | Message::Lost {id: sender}
+                    if sender == from => {
+                    self.broadcast(&message, Some(from));
+                    self.apply(message);
+                }
+                _ => {}
+            }
+        }
+
+        for id in closed {
+            if let Some(peer) = self.peer_mut(id) {
+                peer.connection = None;
+            }
+            self.broadcast(&Message::Gone {id}, None);
+            self.apply(Message::Gone {id});
+            if !matches!(self.state, NetState::Playing) {
+                self.broadcast_lobby();
+                self.try_start();
+            }
+        }
+    }
+
+    fn poll_host(&mut self) {
+        let (messages, closed) = match &mut self.role {
+            Role::Client {host} => (host.receive(), host.is_closed()),
+            Role::Host {..} => return
+        };
+        for message in messages {
+            match message {
+                Message::Welcome {id} => {
+                    self.id = id;
+                    self.state = NetState::Lobby;
+                }
+                Message::Refuse {reason} => {
+                    let text = match reason.as_str() {
+                        "version" => tr(Msg::OtherVersion).to_string(),
+                        "full" => tr(Msg::GameFull).to_string(),
+                        "busy" => tr(Msg::GameBusy).to_string(),
+                        _ => reason
+                    };
+                    self.state = NetState::Closed(text);
+                }
+                Message::Lobby {players} => {
+                    // Snapshots of the last game stay until the next one
+                    let mut peers = Vec::new();
+                    for (id, name, ready) in players {
+                        let mut peer = self.peers.iter().position(|peer| peer.id == id)
+                            .map(|idx| self.peers.remove(idx))
+                            .unwrap_or_else(|| Peer::new(id, name.clone(), None));
+                        peer.name = name;
+                        peer.ready = ready;
+                        peers.push(peer);
+                    }
+                    if matches!(self.state, NetState::Playing) {
+                        // Players who left mid game stay in it as gone
+                        peers.append(&mut self.peers);
+                        peers.sort_by_key(|peer| peer.id);
+                    }
+                    self.peers = peers;
+                }
+                // A game which can not be played here the way the host
+                // plays it is left
+                Message::Start {seed, glass_size, piece_set, pieces, players} => match PieceSet::find(&piece_set) {
+                    _ if !GLASS_WIDTH_RANGE.contains(&glass_size.0) || !GLASS_HEIGHT_RANGE.contains(&glass_size.1) => {
+                        self.leave(tr_args(Msg::NoGlassSize, &[&glass_size.0, &glass_size.1]));
+                    }
+                    Some(set) if set.fingerprint() == pieces => self.start(seed, glass_size, set, players),
+                    Some(_) => self.leave(tr_args(Msg::OtherPieceSet, &[&piece_set])),
+                    None => self.leave(tr_args(Msg::NoPieceSet, &[&piece_set]))
+                },
+                message => self.apply(message)
+            }
+        }
+        if closed && !matches!(self.state, NetState::Closed(_)) {
+            self.state = NetState::Closed(tr(Msg::ConnectionLost).to_string());
+        }
+    }
+
+    // Client only: closes the connection to the host, the text says why
+    fn leave(&mut self, text: String) {
+        self.state = NetState::Closed(text);
+        if let Role::Client {host} = &mut self.role {
+            host.close();
+        }
+    }
+
+    // Game messages, the same on the host and on the clients
+    fn apply(&mut self, message: Message) {
+        let own_id = self.id;
+        let playing = matches!(self.state, NetState::Playing);
+        match message {
+            Message::Garbage {to, rows, ..} if to == own_id && playing => {
+                if let Some(game) = &mut self.game {
+                    game.garbage.push(rows);
+                }
+            }
+            Message::Snapshot {id, lines, incoming, rows} if id != own_id => {
+                if let Some(peer) = self.peer_mut(id) {
+                    peer.lines = lines;
+                    peer.incoming = incoming;
+                    peer.rows = rows;
+                }
+            }
+            Message::Lost {id} => {
+                if let Some(peer) = self.peer_mut(id) {
+                    peer.lost = true;
+                }
+            }
+            Message::Gone {id} => {
+                if playing {
+                    if let Some(peer) = self.peer_mut(id) {
+                        peer.lost = true;
+                        peer.gone = true;
+                    }
+                } else {
+                    self.peers.retain(|peer| peer.id != id);
+                }
+            }
+            _ => {}
+        }
+        self.check_over();
+    }
+
+    // Host only: the game starts once everybody is ready
+    fn try_start(&mut self) {
+        let all_ready = self.peers.iter().filter(|peer| !peer.gone).count() >= 2
+            && self.peers.iter().all(|peer| peer.ready || peer.gone);
+        if !self.is_host() || !all_ready || matches!(self.state, NetState::Playing) {
+            return;
+        }
+        // Players who left the last game are not in the next one
+        self.peers.retain(|peer| !peer.gone);
+        let seed = random::new_seed();
+        let glass_size = (self.settings.glass_width, self.settings.glass_height);
+        let pieces = self.settings.piece_set();
+        let players: Vec<usize> = self.peers.iter().map(|peer| peer.id).collect();
+        let start = Message::Start {
+            seed,
+            glass_size,
+            piece_set: pieces.name.clone(),
+            pieces: pieces.fingerprint(),
+            players: players.clone()
+        };
+        self.broadcast(&start, None);
+        self.start(seed, glass_size, pieces, players);
+    }
+
+    fn start(&mut self, seed: u64, glass_size: (usize, usize), pieces: PieceSet, players: Vec<usize>) {
+        for peer in &mut self.peers {
+            peer.ready = false;
+            peer.lost = false;
+            peer.lines = 0;
+            peer.incoming = 0;
+            peer.rows.clear();
+        }
+        self.game = Some(Match {
+            field: Field::new(pieces, seed, glass_size, GRAVITY_CURVE),
+            garbage: Garbage::new(seed),
+            pending_actions: Vec::new(),
+            players,
+            seed,
+            frames: 0,
+            sent: 0,
+            snapshot: None
+        });
+        self.update_layouts();
+        self.state = NetState::Playing;
+    }
+
+    fn update_layouts(&mut self) {
+        if let Some(game) = &self.game {
+            let glass = &game.field.glass;
+            self.layouts = Layout::side_by_side(self.screen_canvas.width(), self.screen_canvas.height(),
+                                                glass.width(), glass.height(), game.players.len());
+        }
+    }
+
+    fn handle_resize(&mut self) {
+        if let Some((width, height)) = ioscreen::take_resize() {
+            self.screen_canvas = ioscreen::Canvas::new(width, height);
+            self.update_layouts();
+            ioscreen::clear_screen();
+        }
+    }
+
+    // Players still in the game, in the order they were at the start
+    fn alive(&self) -> Vec<usize> {
+        let players = self.game.as_ref().map_or(&[][..], |game| &game.players[..]);
+        players.iter().copied().filter(|id| self.peer(*id).is_some_and(|peer| !peer.lost)).collect()
+    }
+
+    fn check_over(&mut self) {
+        let alive = self.alive();
+        if matches!(self.state, NetState::Playing) && alive.len() <= 1 {
+            self.state = NetState::Over(alive.first().copied());
+        }
+    }
+
+    // Garbage goes to the next player after this one who is still in
+    fn garbage_target(&self) -> Option<usize> {
+        let alive = self.alive();
+        alive.iter().copied().find(|id| *id > self.id).or_else(|| alive.iter().copied().find(|id| *id != self.id))
+    }
+
+    fn logic_frame(&mut self) {
+        let own_id = self.id;
+        if self.peer(own_id).is_none_or(|peer| peer.lost) {
+            return;
+        }
+        let target = self.garbage_target();
+        let game = match &mut self.game {
+            Some(game) => game,
+            None => return
+        };
+        game.frames += 1;
+        let actions = std::mem::take(&mut game.pending_actions);
+        let events = game.field.update(&actions);
+        let mut topped_out = events.topped_out;
+        let mut outgoing = Vec::new();
+        if events.locked && !topped_out {
+            let (attack, overflowed) = game.garbage.shape_locked(&mut game.field, events.rows_cleared);
+            topped_out = overflowed;
+            if let (Some(to), true) = (target, attack > 0) {
+                game.sent += attack;
+                outgoing.push(Message::Garbage {from: own_id, to, rows: attack});
+            }
+        }
+
+        // The last glass goes out whatever the time
+        if game.frames.is_multiple_of(SNAPSHOT_FRAMES) || topped_out {
+            let snapshot = Message::Snapshot {
+                id: own_id,
+                lines: game.field.score.lines,
+                incoming: game.garbage.rows(),
+                rows: game.field.rows(!topped_out)
+            };
+            if game.snapshot.as_ref() != Some(&snapshot) {
+                game.snapshot = Some(snapshot.clone());
+                outgoing.push(snapshot);
+            }
+        }
+        if topped_out {
+            outgoing.push(Message::Lost {id: own_id});
+        }
+        for message in outgoing {
+            self.send_game(message);
+        }
+        if topped_out {
+            self.apply(Message::Lost {id: own_id});
+        }
+    }
+
+    // Returns false when the game should be left
+    fn handle_key(&mut self, key: Key) -> bool {
+        let action = self.settings.action(key);
+        match &self.state {
+            NetState::Joining if action == Some(Action::Quit) => return false,
+            NetState::Closed(_) => return false,
+            _ => {}
+        }
+        // Leaving ends the game for a host and takes a player out of it, so
+        // it is asked first
+        if let Some(menu) = &mut self.leaving {
+            match menu.handle_key(key, &self.settings) {
+                MenuEvent::Select(LEAVE_QUIT) => return false,
+                MenuEvent::Select(_) | MenuEvent::Back => self.leaving = None,
+                MenuEvent::Quit if menu.selected == LEAVE_QUIT => return false,
+                MenuEvent::Quit => menu.selected = LEAVE_QUIT,
+                _ => {}
+            }
+            return true;
+        }
+        if action == Some(Action::Quit) {
+            let mut menu = Menu::new(tr(Msg::LeaveGame), LEAVE_ITEMS.iter().map(|item| tr(*item).to_string()).collect());
+            menu.selected = LEAVE_QUIT;
+            self.leaving = Some(menu);
+            if let Some(game) = &mut self.game {
+                game.pending_actions.clear();
+            }
+            return true;
+        }
+
+        match &self.state {
+            NetState::Joining | NetState::Closed(_) => {}
+            NetState::Lobby => {
+                if matches!(key, Key::Enter | Key::Char(' ')) || action == Some(Action::Drop) {
+                    self.set_ready();
+                }
+            }
+            NetState::Playing => {
+                // Nobody waits for a paused player, so there is no pause
+                if let (Some(game), Some(action)) = (&mut self.game, action) {
+                    if action != Action::Pause {
+                        game.pending_actions.push(action);
+                    }
+                }
+            }
+            NetState::Over(_) => {
+                if key == Key::Char(' ') {
+                    self.state = NetState::Lobby;
+                    if self.is_host() {
+                        self.peers.retain(|peer| !peer.gone);
+                        self.broadcast_lobby();
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn set_ready(&mut self) {
+        let own_id = self.id;
+        if let Some(peer) = self.peer_mut(own_id) {
+            peer.ready = true;
+        }
+        match &mut self.role {
+            Role::Client {host} => host.send(&Message::Ready),
+            Role::Host {..} => {
+                self.broadcast_lobby();
+                self.try_start();
+            }
+        }
+    }
+
+    fn draw_lobby(&mut self) {
+        let mut lines = vec![tr(Msg::NetworkGame).to_string()];
+        if let Role::Host {port, ..} = &self.role {
+            lines.push(tr_args(Msg::HostPort, &[port]));
+        }
+        lines.push(String::new());
+        for peer in &self.peers {
+            let ready = if peer.ready {tr(Msg::ReadyMark)} else {""};
+            lines.push(format!("{} {}", pad_right(&peer.name, 14), pad_left(ready, 8)));
+        }
+        lines.push(String::new());
+        lines.push(tr(Msg::SpaceToReady).to_string());
+        let mut pause_keys = self.settings.key(Action::Pause).title();
+        if let Some(key) = settings::reserved_key(Action::Pause) {
+            pause_keys = format!("{}/{}", pause_keys, key.title());
+        }
+        lines.push(format!("{}: {}", pause_keys, tr(Msg::NoPause)));
+        lines.push(format!("{}: {}", self.settings.key(Action::Quit).title(), Action::Quit.title()));
+        self.draw_panel(&lines);
+    }
+
+    fn draw_panel(&mut self, lines: &[String]) {
+        let center_x = self.screen_canvas.width() / 2;
+        let center_y = self.screen_canvas.height() / 2;
+        menu::draw_panel(&mut self.screen_canvas, lines, center_x, center_y);
+    }
+
+    // This player first, the others in the order of ids
+    fn draw_players(&mut self) {
+        let game = match &self.game {
+            Some(game) => game,
+            None => return
+        };
+        let own_id = self.id;
+        let mut order = vec![own_id];
+        order.extend(game.players.iter().copied().filter(|id| *id != own_id));
+        let playing = matches!(self.state, NetState::Playing);
+        for (id, layout) in order.into_iter().zip(&self.layouts) {
+            let peer = match self.peers.iter().find(|peer| peer.id == id) {
+                Some(peer) => peer,
+                None => continue
+            };
+            let (left, top) = (layout.glass_left, layout.glass_top);
+            let glass = &game.field.glass;
+            field::draw_border(&mut self.screen_canvas, left, top, glass.width(), glass.height());
+            let (lines, incoming) = if id == own_id {
+                game.field.draw(&mut self.screen_canvas, left, top, playing && !peer.lost);
+                (game.field.score.lines, game.garbage.rows())
+            } else {
+                field::draw_rows(&mut self.screen_canvas, &peer.rows, left, top);
+                (peer.lines, peer.incoming)
+            };
+
+            let title = if peer.lost {
+                format!("{} {}", peer.name, tr(Msg::Out))
+            } else {
+                format!("{} {}", peer.name, lines)
+            };
+            self.screen_canvas.draw_text(&title, left, top - 1);
+            // Incoming rows fill the meter up from the bottom of the glass
+            for y in 0..(incoming as usize).min(glass.height()) {
+                self.screen_canvas.set_colored_char(layout.meter_x(), top + glass.height() - 1 - y, '█', Some(METER_COLOR));
+            }
+        }
+    }
+
+    fn summary_lines(&self, winner: Option<usize>) -> Vec<String> {
+        let title = match winner {
+            Some(id) if id == self.id => tr(Msg::YouWin).to_string(),
+            Some(id) => tr_args(Msg::NamedWinner, &[&self.peer(id).map_or("?", |peer| &peer.name)]),
+            None => tr(Msg::Draw).to_string()
+        };
+        let mut lines = vec![title, String::new()];
+        if let Some(game) = &self.game {
+            // Labels differ in width between languages, values stay aligned
+            let stat = |msg: Msg, value: String| format!("{}{}", pad_right(tr(msg), 12), pad_left(&value, 8));
+            lines.push(stat(Msg::Lines, game.field.score.lines.to_string()));
+            lines.push(stat(Msg::Sent, game.sent.to_string()));
+            lines.push(stat(Msg::Pieces, game.field.score.pieces.to_string()));
+            lines.push(stat(Msg::Time, storage::format_duration(game.frames * 1000 / LOGIC_RATE_HZ)));
+            lines.push(format!("{} {:016x}", tr(Msg::Seed), game.seed));
+        }
+        lines.push(String::new());
+        lines.push(tr(Msg::PressSpace).to_string());
+        lines
+    }
+
+    fn draw_too_small(&mut self) {
+        let glass = match &self.game {
+            Some(game) => &game.field.glass,
+            None => return
+        };
+        let (min_width, min_height) = Layout::side_by_side_min_size(glass.width(), glass.height(), self.layouts.len());
+        let too_small = tr(Msg::TooSmall);
+        let need_size = tr_args(Msg::NeedSize, &[&min_width, &min_height]);
+        let center_x = self.screen_canvas.width() / 2;
+        let center_y = self.screen_canvas.height() / 2;
+        self.screen_canvas.draw_text(too_small, center_x.saturating_sub(text_width(too_small) / 2), center_y.saturating_sub(1));
+        self.screen_canvas.draw_text(&need_size, center_x.saturating_sub(text_width(&need_size) / 2), center_y);
+    }
+
+    fn draw(&mut self) {
+        let too_small = self.layouts.first().is_some_and(|layout| layout.too_small);
+        match &self.state {
+            NetState::Joining => self.draw_panel(&[tr(Msg::NetworkGame).to_string(), String::new(), tr(Msg::Connecting).to_string()]),
+            NetState::Lobby => self.draw_lobby(),
+            NetState::Playing | NetState::Over(_) if too_small => self.draw_too_small(),
+            NetState::Playing => self.draw_players(),
+            NetState::Over(winner) => {
+                let lines = self.summary_lines(*winner);
+                self.draw_players();
+                self.draw_panel(&lines);
+            }
+            NetState::Closed(text) => {
+                let lines = [tr(Msg::NetworkGame).to_string(), String::new(), text.clone(), String::new(),
+                             tr(Msg::AnyKeyToExit).to_string()];
+                self.draw_panel(&lines);
+            }
+        }
+        if let (Some(menu), false) = (&self.leaving, matches!(self.state, NetState::Closed(_))) {
+            let center_x = self.screen_canvas.width() / 2;
+            let center_y = self.screen_canvas.height() / 2;
+            menu.draw(&mut self.screen_canvas, center_x, center_y);
+        }
+        self.screen_canvas.display();
+    }
+
+    pub fn set_broadcast(&mut self, broadcast: Option<Broadcast>) {
+        self.broadcast = broadcast;
+    }
+
+    // Spectators see the glasses in the order they are shown here
+    fn update_broadcast(&mut self) {
+        let broadcast = match &mut self.broadcast {
+            Some(broadcast) => broadcast,
+            None => return
+        };
+        let state = match self.state {
+            NetState::Playing => ViewState::Playing,
+            NetState::Over(_) => ViewState::Over,
+            NetState::Joining | NetState::Lobby | NetState::Closed(_) => ViewState::Idle
+        };
+        let game = match (&self.game, state) {
+            (Some(game), ViewState::Playing | ViewState::Over) => game,
+            _ => {
+                broadcast.idle();
+                return;
+            }
+        };
+        let mut order = vec![self.id];
+        order.extend(game.players.iter().copied().filter(|id| *id != self.id));
+        let boards: Vec<Board> = order.into_iter()
+            .filter_map(|id| self.peers.iter().find(|peer| peer.id == id))
+            .map(|peer| {
+                let (lines, incoming, rows) = if peer.id == self.id {
+                    let with_shape = state == ViewState::Playing && !peer.lost;
+                    (game.field.score.lines, game.garbage.rows(), game.field.rows(with_shape))
+                } else {
+                    (peer.lines, peer.incoming, peer.rows.clone())
+                };
+                Board {name: peer.name.clone(), lines, incoming, rows, out: peer.lost}
+            })
+            .collect();
+        let glass = &game.field.glass;
+        broadcast.update(state, (glass.width(), glass.height()), &boards);
+    }
+
+    // Runs until the player leaves the game
+    pub fn game_loop(&mut self) {
+        ioscreen::clear_screen();
+        let frame_duration = Duration::from_nanos(1_000_000_000 / LOGIC_RATE_HZ);
+        let mut next_frame = Instant::now();
+
+        loop {
+            // Keys are collected while waiting for the next logic frame
+            let now = Instant::now();
+            if now < next_frame {
+                if let Some(key) = ioscreen::getch(next_frame - now) {
+                    if !self.handle_key(key) {
+                        return;
+                    }
+                }
+                continue;
+            }
+
+            if ioscreen::quit_requested() {
+                return;
+            }
+
+            self.handle_resize();
+            self.poll_network();
+            // The game goes on while the terminal is too small, the others
+            // do not wait
+            let mut frames = 0;
+            while next_frame <= now && frames < MAX_CATCH_UP_FRAMES {
+                if let NetState::Playing = self.state {
+                    self.logic_frame();
+                }
+                next_frame += frame_duration;
+                frames += 1;
+            }
+            if next_frame <= now {
+                next_frame = now + frame_duration;
+            }
+
+            self.screen_canvas.clear();
+            self.draw();
+            self.update_broadcast();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::ShapeState;
+    use std::io::{BufRead, BufReader, Write};
+    use std::thread;
+
+    // Waits for the condition, polling the games is up to it
+    fn wait_for(mut done: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn host() -> (NetGame, String) {
+        let host = NetGame::host(Settings::default(), 0, "ann").unwrap();
+        let port = match &host.role {
+            Role::Host {port, ..} => *port,
+            Role::Client {..} => unreachable!()
+        };
+        (host, format!("127.0.0.1:{}", port))
+    }
+
+    // Host and a client in a game just started
+    fn playing() -> (NetGame, NetGame) {
+        let (mut host, address) = host();
+        let mut client = NetGame::join(Settings::default(), &address, "bob").unwrap();
+        wait_for(|| {
+            host.poll_network();
+            client.poll_network();
+            matches!(client.state, NetState::Lobby) && client.peers.len() == 2
+        });
+        host.set_ready();
+        client.set_ready();
+        wait_for(|| {
+            host.poll_network();
+            client.poll_network();
+            matches!((&host.state, &client.state), (NetState::Playing, NetState::Playing))
+        });
+        (host, client)
+    }
+
+    // Client of a host which sends the given line once the client is in
+    fn started_by(line: &str) -> NetGame {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut client = NetGame::join(Settings::default(), &address, "bob").unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut hello = String::new();
+        BufReader::new(&stream).read_line(&mut hello).unwrap();
+        assert!(hello.starts_with("HELLO "));
+        stream.write_all(format!("WELCOME 1\nLOBBY 0,ann,1 1,bob,1\n{}\n", line).as_bytes()).unwrap();
+        wait_for(|| {
+            client.poll_network();
+            !matches!(client.state, NetState::Joining | NetState::Lobby)
+        });
+        client
+    }
+
+    fn closed_text(game: &NetGame) -> &str {
+        match &game.state {
+            NetState::Closed(text) => text,
+            _ => ""
+        }
+    }
+
+    #[test]
+    fn players_ready_in_the_lobby_start_the_same_game() {
+        let (host, client) = playing();
+        assert_eq!(client.id, 1);
+        let names: Vec<&str> = client.peers.iter().map(|peer| peer.name.as_str()).collect();
+        assert_eq!(names, ["ann", "bob"]);
+        let (host_game, client_game) = (host.game.as_ref().unwrap(), client.game.as_ref().unwrap());
+        assert_eq!(host_game.seed, client_game.seed);
+        assert_eq!(host_game.players, [HOST_ID, 1]);
+        assert_eq!(client_game.players, [HOST_ID, 1]);
+        assert_eq!(host_game.field.glass.width(), client_game.field.glass.width());
+    }
+
+    #[test]
+    fn players_of_other_versions_are_refused() {
+        let (mut host, address) = host();
+        let mut connection = Connection::new(TcpStream::connect(&address).unwrap()).unwrap();
+        connection.send(&Message::Hello {version: PROTOCOL_VERSION - 1, name: "bob".to_string()});
+        let mut received = Vec::new();
+        wait_for(|| {
+            host.poll_network();
+            received.extend(connection.receive());
+            connection.is_closed()
+        });
+        assert!(received == [Message::Refuse {reason: "version".to_string()}]);
+        assert_eq!(host.peers.len(), 1);
+    }
+
+    #[test]
+    fn silent_connections_are_dropped() {
+        let (mut host, address) = host();
+        let mut stream = TcpStream::connect(&address).unwrap();
+        wait_for(|| {
+            host.poll_network();
+            matches!(&host.role, Role::Host {joining, ..} if !joining.is_empty())
+        });
+        if let Role::Host {joining, ..} = &mut host.role {
+            joining[0].1 -= Duration::from_secs(HELLO_TIMEOUT_SECS);
+        }
+        host.poll_network();
+        let mut line = String::new();
+        assert_eq!(BufReader::new(&mut stream).read_line(&mut line).unwrap_or(0), 0);
+        assert!(matches!(&host.role, Role::Host {joining, ..} if joining.is_empty()));
+    }
+
+    #[test]
+    fn games_which_can_not_be_played_here_are_left() {
+        let pieces = Settings::default().piece_set();
+        let start = |size: (usize, usize), name: &str, fingerprint: u64| {
+            let players = vec![HOST_ID, 1];
+            Message::Start {seed: 1, glass_size: size, piece_set: name.to_string(), pieces: fingerprint, players}.to_line()
+        };
+
+        let client = started_by(&start((10, 20), &pieces.name, pieces.fingerprint()));
+        assert!(matches!(client.state, NetState::Playing));
+        let client = started_by(&start((100, 20), &pieces.name, pieces.fingerprint()));
+        assert_eq!(closed_text(&client), tr_args(Msg::NoGlassSize, &[&100, &20]));
+        let client = started_by(&start((10, 20), &pieces.name, !pieces.fingerprint()));
+        assert_eq!(closed_text(&client), tr_args(Msg::OtherPieceSet, &[&pieces.name]));
+        let client = started_by(&start((10, 20), "nonominoes", 0));
+        assert_eq!(closed_text(&client), tr_args(Msg::NoPieceSet, &[&"nonominoes"]));
+    }
+
+    #[test]
+    fn cleared_rows_reach_the_other_player() {
+        let (mut host, mut client) = playing();
+        // The host drops an I into a well four rows deep
+        let game = host.game.as_mut().unwrap();
+        let (width, height) = (game.field.glass.width(), game.field.glass.height());
+        for y in height - 4..height {
+            for x in 1..width {
+                game.field.glass.fill(x, y);
+            }
+        }
+        game.field.shape = ShapeState {piece: game.field.pieces.piece_index("I").unwrap(), position: 0, x: 0, y: 0};
+        game.pending_actions.push(Action::Drop);
+        host.logic_frame();
+        assert_eq!(host.game.as_ref().unwrap().sent, 4);
+        wait_for(|| {
+            client.poll_network();
+            client.game.as_ref().unwrap().garbage.rows() == 4
+        });
+    }
+
+    #[test]
+    fn the_last_player_in_wins() {
+        let (mut host, mut client) = playing();
+        let game = client.game.as_mut().unwrap();
+        game.garbage.push(100);
+        game.pending_actions.push(Action::Drop);
+        client.logic_frame();
+        assert!(matches!(client.state, NetState::Over(Some(HOST_ID))));
+        wait_for(|| {
+            host.poll_network();
+            matches!(host.state, NetState::Over(Some(HOST_ID)))
+        });
+        assert!(host.peer(1).unwrap().lost);
+    }
+
+    #[test]
+    fn players_gone_are_out_of_the_game() {
+        let (mut host, client) = playing();
+        drop(client);
+        wait_for(|| {
+            host.poll_network();
+            matches!(host.state, NetState::Over(Some(HOST_ID)))
+        });
+        assert!(host.peer(1).unwrap().gone);
+
+        // And the players see the host go
+        let (host, mut client) = playing();
+        drop(host);
+        wait_for(|| {
+            client.poll_network();
+            matches!(client.state, NetState::Closed(_))
+        });
+        assert_eq!(closed_text(&client), tr(Msg::ConnectionLost));
+    }
+}
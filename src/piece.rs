@@ -0,0 +1,515 @@
+// Falling pieces as data: a piece is a polyomino with a list of rotation
+// states, pieces come in named sets. The built in sets are below, more can
+// be added or the built in ones replaced in pieces.cfg in the config directory
+//
+// Sets are written as
+//
+//   set = easy
+//   piece = domino
+//   color = green
+//   weight = 2
+//   rotation = ##
+//   rotation = #/#
+//
+// Rows of a rotation are separated by '/', '#' is a cell and '.' is empty.
+// Cells keep their place in the picture, so empty rows or columns move the
+// piece within its box. A piece given with one rotation only gets the other
+// ones by turning it clockwise. Pieces without a colour are drawn in the
+// colour of the terminal, the weight is how often a piece comes compared to
+// the others of the set and is 1 when left out
+use crate::board::MAX_BOARD_WIDTH;
+use crate::ioscreen::Color;
+use crate::random::Random;
+use crate::storage;
+use std::fs;
+use std::path::PathBuf;
+
+const PIECES_FILE: &str = "pieces.cfg";
+pub const DEFAULT_PIECE_SET: &str = "tetromino";
+
+const BUILTIN_SETS: &str = "
+set = tetromino
+piece = I
+color = cyan
+rotation = #/#/#/#
+rotation = ..../####
+piece = J
+color = blue
+rotation = .#/.#/##
+rotation = #../###
+rotation = ##/#./#.
+rotation = ###/..#
+piece = L
+color = orange
+rotation = #./#./##
+rotation = ###/#..
+rotation = ##/.#/.#
+rotation = ..#/###
+piece = O
+color = yellow
+rotation = ##/##
+piece = S
+color = green
+rotation = .##/##.
+rotation = #./##/.#
+piece = T
+color = magenta
+rotation = ###/.#.
+rotation = .#/##/.#
+rotation = .#./###
+rotation = #./##/#.
+piece = Z
+color = red
+rotation = ##./.##
+rotation = .#/##/#.
+
+set = pentomino
+piece = F
+color = red
+rotation = .##/##./.#.
+piece = F'
+color = orange
+rotation = ##./.##/.#.
+piece = I
+color = cyan
+rotation = #/#/#/#/#
+piece = L
+color = orange
+rotation = #./#./#./##
+piece = L'
+color = blue
+rotation = .#/.#/.#/##
+piece = N
+color = green
+rotation = .#/.#/##/#.
+piece = N'
+color = red
+rotation = #./#./##/.#
+piece = P
+color = yellow
+rotation = ##/##/#.
+piece = P'
+color = purple
+rotation = ##/##/.#
+piece = T
+color = magenta
+rotation = ###/.#./.#.
+piece = U
+color = yellow
+rotation = #.#/###
+piece = V
+color = blue
+rotation = #../#../###
+piece = W
+color = green
+rotation = #../##./.##
+piece = X
+color = white
+rotation = .#./###/.#.
+piece = Y
+color = cyan
+rotation = .#/##/.#/.#
+piece = Y'
+color = purple
+rotation = #./##/#./#.
+piece = Z
+color = red
+rotation = ##./.#./.##
+piece = Z'
+color = green
+rotation = .##/.#./##.
+
+set = extras
+piece = I
+color = cyan
+weight = 6
+rotation = #/#/#/#
+rotation = ..../####
+piece = J
+color = blue
+weight = 6
+rotation = .#/.#/##
+rotation = #../###
+rotation = ##/#./#.
+rotation = ###/..#
+piece = L
+color = orange
+weight = 6
+rotation = #./#./##
+rotation = ###/#..
+rotation = ##/.#/.#
+rotation = ..#/###
+piece = O
+color = yellow
+weight = 6
+rotation = ##/##
+piece = S
+color = green
+weight = 6
+rotation = .##/##.
+rotation = #./##/.#
+piece = T
+color = magenta
+weight = 6
+rotation = ###/.#.
+rotation = .#/##/.#
+rotation = .#./###
+rotation = #./##/#.
+piece = Z
+color = red
+weight = 6
+rotation = ##./.##
+rotation = .#/##/#.
+piece = domino
+color = grey
+rotation = ##
+piece = I3
+color = white
+rotation = ###
+piece = V3
+color = purple
+rotation = ##/#.
+piece = X
+color = white
+rotation = .#./###/.#.
+
+set = easy
+piece = monomino
+color = yellow
+rotation = #
+piece = domino
+color = green
+rotation = ##
+rotation = #/#
+piece = I3
+color = cyan
+rotation = ###
+rotation = #/#/#
+piece = V3
+color = magenta
+rotation = ##/#.
+";
+
+type Rotations = Vec<Vec<(usize, usize)>>;
+
+#[derive(Clone)]
+pub struct Piece {
+    // One word, saved games refer to pieces by it
+    pub name: String,
+    // Cells of every rotation as (x, y) within the box of the piece
+    pub rotations: Rotations,
+    pub color: Option<Color>,
+    pub weight: u32,
+    // Rows of every rotation as bit masks for Board::fits_rows
+    masks: Vec<Vec<u64>>
+}
+
+impl Piece {
+    pub fn rotation_count(&self) -> usize {
+        self.rotations.len()
+    }
+
+    pub fn rows(&self, rotation: usize) -> &[u64] {
+        &self.masks[rotation]
+    }
+
+    // Cells of the rotation with the box at x, y
+    pub fn cells(&self, rotation: usize, x: usize, y: usize) -> Vec<(usize, usize)> {
+        self.rotations[rotation].iter().map(|(cell_x, cell_y)| (x + cell_x, y + cell_y)).collect()
+    }
+
+    pub fn width(&self, rotation: usize) -> usize {
+        self.rotations[rotation].iter().map(|(x, _)| x + 1).max().unwrap_or(0)
+    }
+
+    // Pieces spawn in the middle, pushed left where the glass is too narrow
+    // for them
+    pub fn spawn_x(&self, rotation: usize, glass_width: usize) -> usize {
+        (glass_width / 2).min(glass_width.saturating_sub(self.width(rotation)))
+    }
+}
+
+#[derive(Clone)]
+pub struct PieceSet {
+    pub name: String,
+    pub pieces: Vec<Piece>
+}
+
+fn parse_rotation(picture: &str) -> Option<Vec<(usize, usize)>> {
+    let mut cells = Vec::new();
+    for (y, row) in picture.split('/').enumerate() {
+        for (x, cell) in row.trim().chars().enumerate() {
+            match cell {
+                // Turned, a row becomes a column, neither may be wider than
+                // a board row mask
+                '#' if x >= MAX_BOARD_WIDTH || y >= MAX_BOARD_WIDTH => return None,
+                '#' => cells.push((x, y)),
+                '.' => {}
+                _ => return None
+            }
+        }
+    }
+    if cells.is_empty() {None} else {Some(cells)}
+}
+
+// Moved to the top left corner, cells in reading order
+fn normalize(mut cells: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let left = cells.iter().map(|(x, _)| *x).min().unwrap_or(0);
+    let top = cells.iter().map(|(_, y)| *y).min().unwrap_or(0);
+    for (x, y) in &mut cells {
+        *x -= left;
+        *y -= top;
+    }
+    cells.sort_by_key(|(x, y)| (*y, *x));
+    cells
+}
+
+fn turn(cells: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let height = cells.iter().map(|(_, y)| y + 1).max().unwrap_or(0);
+    normalize(cells.iter().map(|(x, y)| (height - 1 - y, *x)).collect())
+}
+
+// Rows of every rotation as bit masks, bit 0 is the left column of the box
+fn rotation_masks(rotations: &Rotations) -> Vec<Vec<u64>> {
+    rotations.iter().map(|cells| {
+        let height = cells.iter().map(|(_, y)| y + 1).max().unwrap_or(0);
+        let mut rows = vec![0u64; height];
+        for (x, y) in cells {
+            rows[*y] |= 1 << x;
+        }
+        rows
+    }).collect()
+}
+
+// Turns the piece until it looks like one of the rotations it had already
+fn all_rotations(cells: Vec<(usize, usize)>) -> Rotations {
+    let mut rotations = vec![normalize(cells)];
+    loop {
+        let turned = turn(rotations.last().unwrap());
+        if rotations.contains(&turned) {
+            return rotations;
+        }
+        rotations.push(turned);
+    }
+}
+
+impl PieceSet {
+    // Broken pieces are skipped, sets left without pieces are dropped
+    fn parse(text: &str) -> Vec<PieceSet> {
+        let mut sets: Vec<PieceSet> = Vec::new();
+        // Piece being read and whether it is still fine
+        let mut piece: Option<(Piece, bool)> = None;
+        let finish_piece = |sets: &mut Vec<PieceSet>, piece: Option<(Piece, bool)>| {
+            if let (Some(set), Some((mut piece, true))) = (sets.last_mut(), piece) {
+                if piece.rotations.len() == 1 {
+                    piece.rotations = all_rotations(piece.rotations.remove(0));
+                }
+                piece.masks = rotation_masks(&piece.rotations);
+                if !piece.rotations.is_empty() && !set.pieces.iter().any(|known| known.name == piece.name) {
+                    set.pieces.push(piece);
+                }
+            }
+        };
+
+        for (name, value) in storage::parse_key_values(text) {
+            if name == "set" {
+                finish_piece(&mut sets, piece.take());
+                sets.push(PieceSet {name: value, pieces: Vec::new()});
+            } else if name == "piece" {
+                finish_piece(&mut sets, piece.take());
+                let valid = !value.is_empty() && !value.contains(char::is_whitespace);
+                piece = Some((Piece {name: value, rotations: Vec::new(), color: None, weight: 1, masks: Vec::new()}, valid));
+            } else if let Some((piece, valid)) = &mut piece {
+                if name == "rotation" {
+                    match parse_rotation(&value) {
+                        Some(cells) => piece.rotations.push(cells),
+                        None => *valid = false
+                    }
+                } else if name == "color" {
+                    piece.color = Color::from_name(&value);
+                    *valid &= piece.color.is_some();
+                } else if name == "weight" {
+                    match value.parse::<u32>() {
+                        Ok(weight) if weight > 0 => piece.weight = weight,
+                        _ => *valid = false
+                    }
+                }
+            }
+        }
+        finish_piece(&mut sets, piece);
+        sets.retain(|set| !set.pieces.is_empty() && !set.name.contains(char::is_whitespace));
+        sets
+    }
+
+    fn user_file() -> Option<PathBuf> {
+        storage::config_dir().map(|dir| dir.join(PIECES_FILE))
+    }
+
+    // Built in sets first, a set of the user file with a known name
+    // replaces the built in one
+    pub fn all() -> Vec<PieceSet> {
+        let mut sets = Self::parse(BUILTIN_SETS);
+        let user_text = Self::user_file().and_then(|path| fs::read_to_string(path).ok()).unwrap_or_default();
+        for user_set in Self::parse(&user_text) {
+            match sets.iter_mut().find(|set| set.name == user_set.name) {
+                Some(set) => *set = user_set,
+                None => sets.push(user_set)
+            }
+        }
+        sets
+    }
+
+    pub fn find(name: &str) -> Option<PieceSet> {
+        Self::all().into_iter().find(|set| set.name == name)
+    }
+
+    // Built in set of the name, whatever the user file says
+    pub fn builtin(name: &str) -> Option<PieceSet> {
+        Self::parse(BUILTIN_SETS).into_iter().find(|set| set.name == name)
+    }
+
+    pub fn standard() -> PieceSet {
+        Self::builtin(DEFAULT_PIECE_SET).unwrap()
+    }
+
+    // False for a set the user file changed, colours aside
+    pub fn is_builtin(&self) -> bool {
+        let builtin = match Self::builtin(&self.name) {
+            Some(builtin) => builtin,
+            None => return false
+        };
+        self.pieces.len() == builtin.pieces.len() && self.pieces.iter().zip(&builtin.pieces).all(|(piece, builtin)| {
+            piece.rotations == builtin.rotations && piece.weight == builtin.weight
+        })
+    }
+
+    // Index of a random piece, a set where every weight is 1 takes one
+    // number below the piece count, like any uniform pick would
+    pub fn random_piece(&self, random: &mut Random) -> usize {
+        let total: u32 = self.pieces.iter().map(|piece| piece.weight).sum();
+        let mut left = random.below(total as usize) as u32;
+        for (idx, piece) in self.pieces.iter().enumerate() {
+            if left < piece.weight {
+                return idx;
+            }
+            left -= piece.weight;
+        }
+        self.pieces.len() - 1
+    }
+
+    // FNV-1a hash of what makes a game with the set: the order of the
+    // pieces, their rotations and weights. Names and colours are left out
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut add = |value: usize| {
+            for byte in (value as u64).to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+            }
+        };
+        for piece in &self.pieces {
+            add(piece.weight as usize);
+            add(piece.rotations.len());
+            for cells in &piece.rotations {
+                add(cells.len());
+                for (x, y) in cells {
+                    add(*x);
+                    add(*y);
+                }
+            }
+        }
+        hash
+    }
+
+    pub fn piece_index(&self, name: &str) -> Option<usize> {
+        self.pieces.iter().position(|piece| piece.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_rotation_is_turned_into_all_of_them() {
+        let sets = PieceSet::parse("set = test\npiece = T\nrotation = ###/.#.\npiece = O\nrotation = ##/##\npiece = I\nrotation = ####\n");
+        let pieces = &sets[0].pieces;
+        assert_eq!(pieces.iter().map(|piece| piece.rotation_count()).collect::<Vec<_>>(), [4, 1, 2]);
+        // Clockwise: the stem of the T points left after one turn
+        assert_eq!(pieces[0].rotations[1], [(1, 0), (0, 1), (1, 1), (1, 2)]);
+        assert_eq!(pieces[0].rows(1), [0b10, 0b11, 0b10]);
+    }
+
+    #[test]
+    fn given_rotations_keep_their_place_in_the_box() {
+        let sets = PieceSet::parse("set = test\npiece = I\nrotation = .#/.#\nrotation = ../##\n");
+        let piece = &sets[0].pieces[0];
+        assert_eq!(piece.rotations, [vec![(1, 0), (1, 1)], vec![(0, 1), (1, 1)]]);
+        assert_eq!(piece.rows(1), [0, 0b11]);
+    }
+
+    #[test]
+    fn broken_pieces_are_skipped() {
+        let text = "\
+set = test
+piece = good
+color = red
+weight = 3
+rotation = ##
+piece = letter
+rotation = #x
+piece = paint
+color = plaid
+rotation = #
+piece = zero
+weight = 0
+rotation = #
+piece = two words
+rotation = #
+piece = none
+piece = good
+rotation = #
+piece = wide
+rotation = ";
+        let text = format!("{}{}\n", text, "#".repeat(MAX_BOARD_WIDTH + 1));
+        let sets = PieceSet::parse(&text);
+        assert_eq!(sets.len(), 1);
+        let pieces = &sets[0].pieces;
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].name, "good");
+        assert!(pieces[0].color == Some(Color::Red));
+        assert_eq!(pieces[0].weight, 3);
+    }
+
+    #[test]
+    fn sets_without_pieces_are_dropped() {
+        let sets = PieceSet::parse("piece = lost\nrotation = #\nset = empty\nset = two words\npiece = dot\nrotation = #\nset = fine\npiece = dot\nrotation = #\n");
+        assert_eq!(sets.iter().map(|set| set.name.as_str()).collect::<Vec<_>>(), ["fine"]);
+    }
+
+    #[test]
+    fn builtin_sets_parse() {
+        let sets = PieceSet::parse(BUILTIN_SETS);
+        let counts: Vec<(&str, usize)> = sets.iter().map(|set| (set.name.as_str(), set.pieces.len())).collect();
+        assert_eq!(counts, [("tetromino", 7), ("pentomino", 18), ("extras", 11), ("easy", 4)]);
+        assert!(PieceSet::standard().pieces.iter().all(|piece| piece.color.is_some()));
+    }
+
+    #[test]
+    fn fingerprint_follows_the_rules_only() {
+        let standard = PieceSet::standard();
+        let mut recoloured = standard.clone();
+        recoloured.pieces[0].color = None;
+        recoloured.pieces[0].name = "long".to_string();
+        assert_eq!(recoloured.fingerprint(), standard.fingerprint());
+
+        let mut heavier = standard.clone();
+        heavier.pieces[0].weight = 2;
+        assert_ne!(heavier.fingerprint(), standard.fingerprint());
+        let mut turned = standard.clone();
+        turned.pieces[0].rotations.swap(0, 1);
+        assert_ne!(turned.fingerprint(), standard.fingerprint());
+        let mut swapped = standard.clone();
+        swapped.pieces.swap(0, 1);
+        assert_ne!(swapped.fingerprint(), standard.fingerprint());
+    }
+}
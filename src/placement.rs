@@ -0,0 +1,215 @@
+// Every place a piece can lock at from where it is now, with the shortest
+// way there. Moves follow the rules of the game: shifts and rotations which
+// do not fit are ignored, rotations have no kicks, and the piece goes down
+// only by gravity. Tucks under overhangs and spins into slots are found too
+use crate::board::Board;
+use crate::piece::Piece;
+use crate::settings::Action;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Move {
+    Left,
+    Right,
+    Rotate,
+    // One row of falling, the player waits for gravity to do it
+    Down
+}
+
+impl Move {
+    // None for falling, no key does it
+    pub fn action(&self) -> Option<Action> {
+        match self {
+            Self::Left => Some(Action::Left),
+            Self::Right => Some(Action::Right),
+            Self::Rotate => Some(Action::Rotate),
+            Self::Down => None
+        }
+    }
+}
+
+// Where the piece locks and the fewest moves taking it there. A row of
+// falling counts as a move, and the piece is dropped after the last one
+#[derive(Clone, PartialEq)]
+pub struct Placement {
+    pub rotation: usize,
+    pub x: usize,
+    pub y: usize,
+    pub moves: Vec<Move>
+}
+
+// Breadth first over (rotation, x, y), so the first way found to any lock
+// position is a shortest one. Placements come in the order of their paths,
+// shorter first
+pub fn reachable_placements(board: &Board, piece: &Piece, start: (usize, usize, usize)) -> Vec<Placement> {
+    let (width, height, rotations) = (board.width(), board.height(), piece.rotation_count());
+    let fits = |(rotation, x, y): (usize, usize, usize)| board.fits_rows(piece.rows(rotation), x, y);
+    if !fits(start) {
+        return Vec::new();
+    }
+
+    let index = |(rotation, x, y): (usize, usize, usize)| (rotation * height + y) * width + x;
+    // State the search came from and the move, for every state seen
+    let mut came_from: Vec<Option<(usize, usize, usize, Move)>> = vec![None; rotations * width * height];
+    let mut seen = vec![false; rotations * width * height];
+    let mut locked = vec![false; rotations * width * height];
+    let mut queue = VecDeque::from([start]);
+    seen[index(start)] = true;
+    let mut found = Vec::new();
+
+    while let Some(state) = queue.pop_front() {
+        let (rotation, x, mut drop_y) = state;
+        while fits((rotation, x, drop_y + 1)) {
+            drop_y += 1;
+        }
+        if !locked[index((rotation, x, drop_y))] {
+            locked[index((rotation, x, drop_y))] = true;
+            let mut moves = Vec::new();
+            let mut at = state;
+            while let Some((from_rotation, from_x, from_y, step)) = came_from[index(at)] {
+                moves.push(step);
+                at = (from_rotation, from_x, from_y);
+            }
+            moves.reverse();
+            found.push(Placement {rotation, x, y: drop_y, moves});
+        }
+
+        let (_, x, y) = state;
+        let next_states = [
+            (Move::Left, x.checked_sub(1).map(|left| (rotation, left, y))),
+            (Move::Right, Some((rotation, x + 1, y))),
+            (Move::Rotate, Some(((rotation + 1) % rotations, x, y))),
+            (Move::Down, Some((rotation, x, y + 1)))
+        ];
+        for (step, next) in next_states {
+            if let Some(next) = next.filter(|next| fits(*next)) {
+                if !seen[index(next)] {
+                    seen[index(next)] = true;
+                    came_from[index(next)] = Some((rotation, x, y, step));
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceSet;
+
+    // Rows of the picture are rows of the board, '#' is a frozen cell
+    fn board(picture: &[&str]) -> Board {
+        let mut board = Board::new(picture[0].len(), picture.len());
+        for (y, row) in picture.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                if cell == '#' {
+                    board.fill(x, y);
+                }
+            }
+        }
+        board
+    }
+
+    fn piece(name: &str) -> Piece {
+        let pieces = PieceSet::standard();
+        pieces.pieces[pieces.piece_index(name).unwrap()].clone()
+    }
+
+    fn find(placements: &[Placement], (rotation, x, y): (usize, usize, usize)) -> Option<&Placement> {
+        placements.iter().find(|placement| (placement.rotation, placement.x, placement.y) == (rotation, x, y))
+    }
+
+    // Plays the moves the way the game does and drops the piece after them
+    fn follow(board: &Board, piece: &Piece, start: (usize, usize, usize), moves: &[Move]) -> (usize, usize, usize) {
+        let fits = |(rotation, x, y): (usize, usize, usize)| board.fits_rows(piece.rows(rotation), x, y);
+        let (mut rotation, mut x, mut y) = start;
+        for step in moves {
+            let next = match step {
+                Move::Left => (rotation, x - 1, y),
+                Move::Right => (rotation, x + 1, y),
+                Move::Rotate => ((rotation + 1) % piece.rotation_count(), x, y),
+                Move::Down => (rotation, x, y + 1)
+            };
+            assert!(fits(next));
+            (rotation, x, y) = next;
+        }
+        while fits((rotation, x, y + 1)) {
+            y += 1;
+        }
+        (rotation, x, y)
+    }
+
+    #[test]
+    fn pieces_tuck_under_overhangs() {
+        let board = board(&[
+            "......",
+            "......",
+            "......",
+            "##....",
+            "......",
+            "......"
+        ]);
+        let o = piece("O");
+        let placements = reachable_placements(&board, &o, (0, 2, 0));
+        assert!(placements[0] == Placement {rotation: 0, x: 2, y: 4, moves: Vec::new()});
+        let on_ledge = find(&placements, (0, 0, 1)).unwrap();
+        assert!(on_ledge.moves == [Move::Left, Move::Left]);
+        // Down to the floor first, then in under the ledge
+        let tucked = find(&placements, (0, 0, 4)).unwrap();
+        assert_eq!(tucked.moves.len(), 6);
+        assert_eq!(tucked.moves.iter().filter(|step| **step == Move::Down).count(), 4);
+        assert_eq!(follow(&board, &o, (0, 2, 0), &tucked.moves), (0, 0, 4));
+    }
+
+    #[test]
+    fn pieces_spin_into_slots() {
+        // The T comes down pointing left and turns under the cell at the top
+        // right, pointing down it does not get past that cell
+        let board = board(&[
+            "...#",
+            "#...",
+            "#..#",
+            "#.##"
+        ]);
+        let t = piece("T");
+        let placements = reachable_placements(&board, &t, (3, 1, 0));
+        assert!(placements[0] == Placement {rotation: 3, x: 1, y: 1, moves: Vec::new()});
+        let spun = find(&placements, (0, 1, 1)).unwrap();
+        assert!(spun.moves == [Move::Down, Move::Rotate]);
+    }
+
+    #[test]
+    fn paths_are_shortest_and_lead_to_their_placements() {
+        let board = board(&[
+            "........",
+            "........",
+            "........",
+            "...##...",
+            "........",
+            "#......#",
+            "##.#..##"
+        ]);
+        for name in ["I", "J", "L", "O", "S", "T", "Z"] {
+            let piece = piece(name);
+            let start = (0, 1, 0);
+            let placements = reachable_placements(&board, &piece, start);
+            assert!(!placements.is_empty());
+            for (idx, placement) in placements.iter().enumerate() {
+                let end = follow(&board, &piece, start, &placement.moves);
+                assert_eq!(end, (placement.rotation, placement.x, placement.y));
+                assert!(find(&placements[..idx], end).is_none());
+                if idx > 0 {
+                    assert!(placements[idx - 1].moves.len() <= placement.moves.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_is_reachable_from_a_start_which_does_not_fit() {
+        let board = board(&["####", "...."]);
+        assert!(reachable_placements(&board, &piece("O"), (0, 0, 0)).is_empty());
+    }
+}
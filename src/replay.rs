@@ -0,0 +1,285 @@
+// Recorded games: the engine is deterministic, so the mode, the seed and
+// the actions of every logic frame are enough to play a game again
+use crate::field::LOCK_DELAY_FRAMES;
+use crate::game::{DEFAULT_GLASS_HEIGHT, DEFAULT_GLASS_WIDTH, GLASS_HEIGHT_RANGE, GLASS_WIDTH_RANGE};
+use crate::gravity::GravityCurve;
+use crate::mode::GameMode;
+use crate::piece::{PieceSet, DEFAULT_PIECE_SET};
+use crate::settings::Action;
+use crate::storage;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const REPLAY_DIR: &str = "replays";
+const REPLAY_EXTENSION: &str = "replay";
+const HEADER: &str = "# rustris replay";
+const VERSION: u32 = 1;
+// Older replays are deleted when a new one is saved
+const MAX_REPLAYS: usize = 20;
+
+// Why a saved replay can not be played
+pub enum LoadError {
+    // Missing, broken or of another version
+    Unreadable,
+    // The piece set is gone or its pieces are not the recorded ones any more
+    PiecesChanged
+}
+
+#[derive(Clone)]
+pub struct Replay {
+    pub mode: GameMode,
+    pub seed: u64,
+    pub glass_width: usize,
+    pub glass_height: usize,
+    // Name of the piece set, the pieces themselves come from the set of
+    // that name when the replay is played, as long as they still have the
+    // recorded fingerprint
+    pub piece_set: String,
+    pub pieces_fingerprint: Option<u64>,
+    // Rules of the engine the game was played with, a later version may
+    // give the mode other ones
+    pub gravity_curve: GravityCurve,
+    pub lock_delay: u32,
+    // Unix time the game ended at
+    pub date: u64,
+    pub frames: u64,
+    pub points: u64,
+    // Logic frame and the actions applied in it, frames without actions
+    // are left out
+    pub inputs: Vec<(u64, Vec<Action>)>
+}
+
+fn action_code(action: Action) -> Option<char> {
+    match action {
+        Action::Left => Some('l'),
+        Action::Right => Some('r'),
+        Action::Rotate => Some('t'),
+        Action::SpeedUp => Some('s'),
+        Action::Drop => Some('d'),
+        // Pausing and quitting never reach the engine
+        Action::Pause | Action::Quit => None
+    }
+}
+
+fn code_action(code: char) -> Option<Action> {
+    match code {
+        'l' => Some(Action::Left),
+        'r' => Some(Action::Right),
+        't' => Some(Action::Rotate),
+        's' => Some(Action::SpeedUp),
+        'd' => Some(Action::Drop),
+        _ => None
+    }
+}
+
+// Inputs are stored as "frames since the previous input:action codes"
+pub fn inputs_to_text(inputs: &[(u64, Vec<Action>)]) -> String {
+    let mut previous_frame = 0;
+    let inputs: Vec<String> = inputs.iter().map(|(frame, actions)| {
+        let codes: String = actions.iter().filter_map(|action| action_code(*action)).collect();
+        let input = format!("{}:{}", frame - previous_frame, codes);
+        previous_frame = *frame;
+        input
+    }).collect();
+    inputs.join(" ")
+}
+
+pub fn inputs_from_text(text: &str) -> Option<Vec<(u64, Vec<Action>)>> {
+    let mut inputs = Vec::new();
+    let mut frame = 0;
+    for input in text.split_whitespace() {
+        let (delta, codes) = input.split_once(':')?;
+        frame += delta.parse::<u64>().ok()?;
+        let actions = codes.chars().map(code_action).collect::<Option<Vec<Action>>>()?;
+        inputs.push((frame, actions));
+    }
+    Some(inputs)
+}
+
+impl Replay {
+    pub fn new(mode: GameMode, seed: u64, glass_size: (usize, usize), pieces: &PieceSet) -> Self {
+        let (glass_width, glass_height) = glass_size;
+        Replay {
+            mode,
+            seed,
+            glass_width,
+            glass_height,
+            piece_set: pieces.name.clone(),
+            pieces_fingerprint: Some(pieces.fingerprint()),
+            gravity_curve: mode.gravity_curve(),
+            lock_delay: LOCK_DELAY_FRAMES,
+            date: 0,
+            frames: 0,
+            points: 0,
+            inputs: Vec::new()
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, actions: &[Action]) {
+        if !actions.is_empty() {
+            self.inputs.push((frame, actions.to_vec()));
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("{}\nversion = {}\n", HEADER, VERSION);
+        text += &format!("mode = {}\n", self.mode.name());
+        text += &format!("seed = {:016x}\n", self.seed);
+        text += &format!("glass = {}x{}\n", self.glass_width, self.glass_height);
+        text += &format!("piece_set = {}\n", self.piece_set);
+        if let Some(fingerprint) = self.pieces_fingerprint {
+            text += &format!("pieces = {:016x}\n", fingerprint);
+        }
+        text += &format!("gravity = {}\n", self.gravity_curve.name());
+        text += &format!("lock_delay = {}\n", self.lock_delay);
+        text += &format!("date = {}\n", self.date);
+        text += &format!("frames = {}\n", self.frames);
+        text += &format!("points = {}\n", self.points);
+        text += &format!("inputs = {}\n", inputs_to_text(&self.inputs));
+        text
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let values = storage::parse_key_values(text);
+        let value = |name: &str| values.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        if value("version")?.parse::<u32>().ok()? != VERSION {
+            return None;
+        }
+
+        // Replays from before the glass size was configurable have no size
+        let glass_size = match value("glass") {
+            Some(glass) => {
+                let (width, height) = glass.split_once('x')?;
+                (width.parse().ok()?, height.parse().ok()?)
+            }
+            None => (DEFAULT_GLASS_WIDTH, DEFAULT_GLASS_HEIGHT)
+        };
+        if !GLASS_WIDTH_RANGE.contains(&glass_size.0) || !GLASS_HEIGHT_RANGE.contains(&glass_size.1) {
+            return None;
+        }
+
+        let mode = GameMode::from_name(value("mode")?)?;
+        let seed = u64::from_str_radix(value("seed")?, 16).ok()?;
+        let mut replay = Replay::new(mode, seed, glass_size, &PieceSet::standard());
+        // So do replays from before piece sets, and the ones from before
+        // the fingerprint take the set of the name as it is
+        replay.piece_set = value("piece_set").unwrap_or(DEFAULT_PIECE_SET).to_string();
+        replay.pieces_fingerprint = match value("pieces") {
+            Some(fingerprint) => Some(u64::from_str_radix(fingerprint, 16).ok()?),
+            None => None
+        };
+        // And replays from before the rules were recorded, they were the
+        // ones of the mode
+        if let Some(gravity) = value("gravity") {
+            replay.gravity_curve = GravityCurve::from_name(gravity)?;
+        }
+        if let Some(lock_delay) = value("lock_delay") {
+            replay.lock_delay = lock_delay.parse().ok().filter(|lock_delay| *lock_delay > 0)?;
+        }
+        replay.date = value("date")?.parse().ok()?;
+        replay.frames = value("frames")?.parse().ok()?;
+        replay.points = value("points")?.parse().ok()?;
+        replay.inputs = inputs_from_text(value("inputs")?)?;
+        Some(replay)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let text = fs::read_to_string(path).map_err(|_| LoadError::Unreadable)?;
+        let replay = Self::from_text(&text).ok_or(LoadError::Unreadable)?;
+        replay.pieces().ok_or(LoadError::PiecesChanged)?;
+        Ok(replay)
+    }
+
+    // Set the game was played with, None when it is not there any more
+    pub fn pieces(&self) -> Option<PieceSet> {
+        let pieces = PieceSet::find(&self.piece_set)?;
+        match self.pieces_fingerprint {
+            Some(fingerprint) if fingerprint != pieces.fingerprint() => None,
+            _ => Some(pieces)
+        }
+    }
+
+    fn dir() -> Option<PathBuf> {
+        storage::data_dir().map(|dir| dir.join(REPLAY_DIR))
+    }
+
+    pub fn save(&self) {
+        let dir = match Self::dir() {
+            Some(dir) => dir,
+            None => return
+        };
+        // Games ending within the same second have different seeds
+        let path = dir.join(format!("{}-{}-{:016x}.{}", self.date, self.mode.name(), self.seed, REPLAY_EXTENSION));
+        if storage::write_atomic(&path, self.to_text().as_bytes()).is_ok() {
+            for old_path in Self::list().into_iter().skip(MAX_REPLAYS) {
+                let _ = fs::remove_file(old_path);
+            }
+        }
+    }
+
+    // Saved replays, the newest first
+    pub fn list() -> Vec<PathBuf> {
+        let entries = match Self::dir().and_then(|dir| fs::read_dir(dir).ok()) {
+            Some(entries) => entries,
+            None => return Vec::new()
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == REPLAY_EXTENSION))
+            .collect();
+        // File names start with the unix time, sorting by them sorts by date
+        paths.sort_by_key(|path| {
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+            stem.split('-').next().and_then(|time| time.parse::<u64>().ok()).unwrap_or(0)
+        });
+        paths.reverse();
+        paths
+    }
+
+    // Actions of the given frame, inputs are looked up from the cursor on
+    pub fn actions(&self, frame: u64, cursor: &mut usize) -> Vec<Action> {
+        match self.inputs.get(*cursor) {
+            Some((input_frame, actions)) if *input_frame == frame => {
+                *cursor += 1;
+                actions.clone()
+            }
+            _ => Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_round_trip() {
+        let mut replay = Replay::new(GameMode::Classic, 0xabc, (10, 20), &PieceSet::standard());
+        replay.gravity_curve = GravityCurve::Tgm;
+        replay.lock_delay = 12;
+        replay.record(5, &[Action::Left, Action::Drop]);
+        let loaded = Replay::from_text(&replay.to_text()).unwrap();
+        assert!(loaded.gravity_curve == GravityCurve::Tgm);
+        assert_eq!(loaded.lock_delay, 12);
+        assert_eq!(loaded.seed, 0xabc);
+        assert_eq!(loaded.inputs.len(), 1);
+    }
+
+    #[test]
+    fn replays_without_rules_get_the_ones_of_the_mode() {
+        let text = "version = 1\nmode = classic\nseed = 0000000000000abc\ndate = 0\nframes = 0\npoints = 0\ninputs = \n";
+        let replay = Replay::from_text(text).unwrap();
+        assert!(replay.gravity_curve == GameMode::Classic.gravity_curve());
+        assert_eq!(replay.lock_delay, LOCK_DELAY_FRAMES);
+        assert!(replay.pieces().is_some());
+    }
+
+    #[test]
+    fn replays_of_changed_pieces_are_refused() {
+        let mut replay = Replay::new(GameMode::Marathon, 1, (10, 20), &PieceSet::standard());
+        assert!(replay.pieces().is_some());
+        replay.pieces_fingerprint = replay.pieces_fingerprint.map(|fingerprint| fingerprint ^ 1);
+        let loaded = Replay::from_text(&replay.to_text()).unwrap();
+        assert!(loaded.pieces().is_none());
+    }
+}
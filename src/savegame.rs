@@ -0,0 +1,210 @@
+// Game left unfinished, written when the player quits and offered to be
+// continued on the next start
+use crate::mode::GameMode;
+use crate::piece::DEFAULT_PIECE_SET;
+use crate::replay;
+use crate::settings::Action;
+use crate::storage;
+use std::fs;
+use std::path::PathBuf;
+
+const SAVE_FILE: &str = "savegame.cfg";
+const HEADER: &str = "# rustris saved game";
+// Saves of any other version are ignored. Version 2 added the speed bonus,
+// the garbage rows and the fingerprint of the piece set, saves of version 1
+// are continued without them
+const VERSION: u32 = 2;
+const V1: u32 = 1;
+
+#[derive(Clone)]
+pub struct SavedGame {
+    pub mode: GameMode,
+    pub seed: u64,
+    pub random_state: u64,
+    pub frames: u64,
+    pub lines: u32,
+    pub level: u32,
+    pub speed_bonus: u32,
+    pub points: u64,
+    pub pieces: u32,
+    // Garbage rows cleared in the dig modes
+    pub garbage_cleared: u32,
+    pub fall_progress: u32,
+    pub lock_frames: u32,
+    pub piece_set: String,
+    // PieceSet::fingerprint of the set, None in saves of version 1
+    pub piece_set_fingerprint: Option<u64>,
+    // Piece name, rotation and position of the falling shape
+    pub shape: (String, usize, usize, usize),
+    // Rows of the glass from the top, '#' is a frozen cell and '.' is empty
+    pub glass: Vec<String>,
+    // Rows of the glass which came in as garbage, None in saves of version 1
+    pub garbage_rows: Option<Vec<usize>>,
+    // Inputs so far, the replay of the game stays complete
+    pub inputs: Vec<(u64, Vec<Action>)>
+}
+
+impl SavedGame {
+    fn path() -> Option<PathBuf> {
+        storage::data_dir().map(|dir| dir.join(SAVE_FILE))
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("{}\nversion = {}\n", HEADER, VERSION);
+        text += &format!("mode = {}\n", self.mode.name());
+        text += &format!("seed = {:016x}\n", self.seed);
+        text += &format!("random = {:016x}\n", self.random_state);
+        text += &format!("frames = {}\n", self.frames);
+        text += &format!("lines = {}\n", self.lines);
+        text += &format!("level = {}\n", self.level);
+        text += &format!("speed_bonus = {}\n", self.speed_bonus);
+        text += &format!("points = {}\n", self.points);
+        text += &format!("pieces = {}\n", self.pieces);
+        text += &format!("garbage_cleared = {}\n", self.garbage_cleared);
+        text += &format!("fall_progress = {}\n", self.fall_progress);
+        text += &format!("lock_frames = {}\n", self.lock_frames);
+        text += &format!("piece_set = {}\n", self.piece_set);
+        if let Some(fingerprint) = self.piece_set_fingerprint {
+            text += &format!("piece_set_fingerprint = {:016x}\n", fingerprint);
+        }
+        let (piece, position, x, y) = &self.shape;
+        text += &format!("shape = {} {} {} {}\n", piece, position, x, y);
+        for row in &self.glass {
+            text += &format!("row = {}\n", row);
+        }
+        if let Some(rows) = &self.garbage_rows {
+            let rows: Vec<String> = rows.iter().map(|y| y.to_string()).collect();
+            text += &format!("garbage_rows = {}\n", rows.join(" "));
+        }
+        text += &format!("inputs = {}\n", replay::inputs_to_text(&self.inputs));
+        text
+    }
+
+    fn from_text(text: &str) -> Option<Self> {
+        let values = storage::parse_key_values(text);
+        let value = |name: &str| values.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        let version = value("version")?.parse::<u32>().ok()?;
+        let added_in_v2 = ["speed_bonus", "garbage_rows", "piece_set_fingerprint"];
+        let known = version == VERSION || version == V1;
+        if !known || (version == VERSION && added_in_v2.iter().any(|name| value(name).is_none())) {
+            return None;
+        }
+
+        let shape: Vec<&str> = value("shape")?.split_whitespace().collect();
+        let shape = match shape.as_slice() {
+            [piece, position, x, y] => {
+                (piece.to_string(), position.parse().ok()?, x.parse().ok()?, y.parse().ok()?)
+            }
+            _ => return None
+        };
+        Some(SavedGame {
+            mode: GameMode::from_name(value("mode")?)?,
+            seed: u64::from_str_radix(value("seed")?, 16).ok()?,
+            random_state: u64::from_str_radix(value("random")?, 16).ok()?,
+            frames: value("frames")?.parse().ok()?,
+            lines: value("lines")?.parse().ok()?,
+            level: value("level")?.parse().ok()?,
+            speed_bonus: value("speed_bonus").unwrap_or("0").parse().ok()?,
+            points: value("points")?.parse().ok()?,
+            pieces: value("pieces")?.parse().ok()?,
+            // Saves from before the dig modes have none
+            garbage_cleared: value("garbage_cleared").unwrap_or("0").parse().ok()?,
+            fall_progress: value("fall_progress")?.parse().ok()?,
+            lock_frames: value("lock_frames")?.parse().ok()?,
+            // Saves from before piece sets were made with the standard one
+            piece_set: value("piece_set").unwrap_or(DEFAULT_PIECE_SET).to_string(),
+            piece_set_fingerprint: match value("piece_set_fingerprint") {
+                Some(fingerprint) => Some(u64::from_str_radix(fingerprint, 16).ok()?),
+                None => None
+            },
+            shape,
+            glass: values.iter().filter(|(key, _)| key == "row").map(|(_, row)| row.clone()).collect(),
+            garbage_rows: match value("garbage_rows") {
+                Some(rows) => Some(rows.split_whitespace().map(|y| y.parse().ok()).collect::<Option<Vec<usize>>>()?),
+                None => None
+            },
+            inputs: replay::inputs_from_text(value("inputs")?)?
+        })
+    }
+
+    // None when there is no save or it can not be read
+    pub fn load() -> Option<Self> {
+        Self::from_text(&fs::read_to_string(Self::path()?).ok()?)
+    }
+
+    pub fn save(&self) {
+        if let Some(path) = Self::path() {
+            let _ = storage::write_atomic(&path, self.to_text().as_bytes());
+        }
+    }
+
+    // A save is continued once only
+    pub fn delete() {
+        if let Some(path) = Self::path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved() -> SavedGame {
+        SavedGame {
+            mode: GameMode::Dig,
+            seed: 0x1234,
+            random_state: 0xfeed,
+            frames: 600,
+            lines: 12,
+            level: 2,
+            speed_bonus: 3,
+            points: 4500,
+            pieces: 40,
+            garbage_cleared: 5,
+            fall_progress: 100,
+            lock_frames: 7,
+            piece_set: DEFAULT_PIECE_SET.to_string(),
+            piece_set_fingerprint: Some(0xabcdef),
+            shape: ("T".to_string(), 1, 4, 2),
+            glass: vec!["....".to_string(), "e.ee".to_string()],
+            garbage_rows: Some(vec![1]),
+            inputs: vec![(10, vec![Action::Left, Action::Rotate]), (25, vec![Action::Drop])]
+        }
+    }
+
+    #[test]
+    fn saves_come_back_from_their_text() {
+        let saved = saved();
+        let text = saved.to_text();
+        let loaded = SavedGame::from_text(&text).unwrap();
+        assert_eq!(loaded.to_text(), text);
+        assert!(loaded.piece_set_fingerprint == saved.piece_set_fingerprint);
+        assert!(loaded.garbage_rows == saved.garbage_rows);
+        assert_eq!(loaded.speed_bonus, saved.speed_bonus);
+    }
+
+    #[test]
+    fn broken_and_other_saves_are_refused() {
+        let text = saved().to_text();
+        assert!(SavedGame::from_text(&text.replace("version = 2", "version = 3")).is_none());
+        assert!(SavedGame::from_text(&text.replace("mode = dig", "mode = golf")).is_none());
+        assert!(SavedGame::from_text(&text.replace("shape = T 1 4 2", "shape = T 1 4")).is_none());
+        assert!(SavedGame::from_text(&text.replace("garbage_rows = 1", "garbage_rows = one")).is_none());
+        // Saves of this version have every key of it
+        let without_fingerprint: String = text.lines().filter(|line| !line.starts_with("piece_set_fingerprint")).map(|line| format!("{}\n", line)).collect();
+        assert!(SavedGame::from_text(&without_fingerprint).is_none());
+    }
+
+    #[test]
+    fn version_1_saves_go_without_the_new_keys() {
+        let text: String = saved().to_text().replace("version = 2", "version = 1").lines()
+            .filter(|line| !["speed_bonus", "garbage_rows", "piece_set_fingerprint"].iter().any(|name| line.starts_with(name)))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let loaded = SavedGame::from_text(&text).unwrap();
+        assert!(loaded.piece_set_fingerprint.is_none());
+        assert!(loaded.garbage_rows.is_none());
+        assert_eq!(loaded.speed_bonus, 0);
+    }
+}
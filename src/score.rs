@@ -0,0 +1,86 @@
+use crate::gravity::{GravityCurve, Leveling};
+
+// Points for 1, 2, 3 and 4 rows cleared at once, multiplied by level + 1
+const LINE_POINTS: [u64; 5] = [0, 40, 100, 300, 1200];
+const LINES_PER_LEVEL: u32 = 10;
+const SECTION_LEVELS: u32 = 100;
+const MAX_SECTION_LEVEL: u32 = 999;
+
+pub struct Score {
+    pub lines: u32,
+    pub level: u32,
+    pub points: u64,
+    pub pieces: u32,
+    // Levels added with the speed up key, the shape falls as fast as on the
+    // level they add up to but points go by the level alone
+    pub speed_bonus: u32,
+    leveling: Leveling
+}
+
+impl Score {
+    pub fn new(curve: &GravityCurve) -> Self {
+        Score {
+            lines: 0,
+            level: curve.first_level(),
+            points: 0,
+            pieces: 0,
+            speed_bonus: 0,
+            leveling: curve.leveling()
+        }
+    }
+
+    pub fn shape_spawned(&mut self) {
+        self.pieces += 1;
+        if self.leveling == Leveling::PerPieceAndLine {
+            let next_level = self.level + 1;
+            if !next_level.is_multiple_of(SECTION_LEVELS) && next_level < MAX_SECTION_LEVEL {
+                self.level = next_level;
+            }
+        }
+    }
+
+    pub fn rows_cleared(&mut self, rows: u32) {
+        if rows == 0 {
+            return;
+        }
+        let points_idx = (rows as usize).min(LINE_POINTS.len() - 1);
+        self.points += LINE_POINTS[points_idx] * (self.level as u64 + 1);
+
+        let old_lines = self.lines;
+        self.lines += rows;
+        match self.leveling {
+            Leveling::PerTenLines => {
+                self.level += self.lines / LINES_PER_LEVEL - old_lines / LINES_PER_LEVEL;
+            }
+            Leveling::PerPieceAndLine => {
+                self.level = (self.level + rows).min(MAX_SECTION_LEVEL);
+            }
+        }
+    }
+
+    pub fn speed_up(&mut self) {
+        self.speed_bonus += 1;
+    }
+
+    // Level the gravity is taken from
+    pub fn speed_level(&self) -> u32 {
+        self.level.saturating_add(self.speed_bonus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speeding_up_scores_nothing() {
+        let mut score = Score::new(&GravityCurve::Guideline);
+        for _ in 0..200 {
+            score.speed_up();
+        }
+        score.rows_cleared(4);
+        assert_eq!(score.level, 1);
+        assert_eq!(score.speed_level(), 201);
+        assert_eq!(score.points, LINE_POINTS[4] * 2);
+    }
+}
@@ -0,0 +1,467 @@
+use crate::game::{DEFAULT_GLASS_HEIGHT, DEFAULT_GLASS_WIDTH, GLASS_HEIGHT_RANGE, GLASS_WIDTH_RANGE};
+use crate::i18n::{self, tr, tr_args, Language, Msg, LANGUAGES};
+use crate::ioscreen::{Canvas, Key};
+use crate::menu::{Menu, MenuEvent};
+use crate::piece::{PieceSet, DEFAULT_PIECE_SET};
+use crate::storage;
+use std::fs;
+use std::path::PathBuf;
+
+const SETTINGS_FILE: &str = "settings.cfg";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Left,
+    Right,
+    Rotate,
+    SpeedUp,
+    Drop,
+    Pause,
+    Quit
+}
+
+pub const ACTIONS: [Action; 7] = [
+    Action::Left, Action::Right, Action::Rotate, Action::SpeedUp, Action::Drop, Action::Pause, Action::Quit
+];
+
+// Esc pauses and space drops whatever the bindings are, so no action can be
+// bound to them
+const RESERVED_KEYS: [(Key, Action); 2] = [(Key::Esc, Action::Pause), (Key::Char(' '), Action::Drop)];
+
+pub fn is_reserved(key: Key) -> bool {
+    RESERVED_KEYS.iter().any(|(reserved, _)| *reserved == key)
+}
+
+pub fn reserved_key(action: Action) -> Option<Key> {
+    RESERVED_KEYS.iter().find(|(_, reserved_action)| *reserved_action == action).map(|(key, _)| *key)
+}
+
+// Keys of the two players sharing the keyboard, by default the first player
+// on the left of it and the second one on the arrows. Pause and quit keep
+// the keys of the single player and work for both
+pub const SPLIT_ACTIONS: [Action; 4] = [Action::Left, Action::Right, Action::Rotate, Action::Drop];
+const DEFAULT_SPLIT_KEYS: [[Key; 4]; 2] = [
+    [Key::Char('a'), Key::Char('d'), Key::Char('w'), Key::Char('s')],
+    [Key::Left, Key::Right, Key::Up, Key::Down]
+];
+const SHARED_ACTIONS: [Action; 2] = [Action::Pause, Action::Quit];
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Rotate => "rotate",
+            Self::SpeedUp => "speed_up",
+            Self::Drop => "drop",
+            Self::Pause => "pause",
+            Self::Quit => "quit"
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        tr(match self {
+            Self::Left => Msg::ActionLeft,
+            Self::Right => Msg::ActionRight,
+            Self::Rotate => Msg::ActionRotate,
+            Self::SpeedUp => Msg::ActionSpeedUp,
+            Self::Drop => Msg::ActionDrop,
+            Self::Pause => Msg::ActionPause,
+            Self::Quit => Msg::ActionQuit
+        })
+    }
+
+    fn default_key(&self) -> Key {
+        match self {
+            Self::Left => Key::Char('7'),
+            Self::Right => Key::Char('9'),
+            Self::Rotate => Key::Char('8'),
+            Self::SpeedUp => Key::Char('4'),
+            Self::Drop => Key::Char('5'),
+            Self::Pause => Key::Char('p'),
+            Self::Quit => Key::Char('q')
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    bindings: Vec<(Action, Key)>,
+    pub show_help: bool,
+    // None follows the locale of the environment
+    pub language: Option<Language>,
+    // Size of the glass in cells, used from the next game on
+    pub glass_width: usize,
+    pub glass_height: usize,
+    // Name of the set the pieces are taken from
+    pub piece_set: String,
+    // Two players send each other garbage rows
+    pub split_garbage: bool,
+    // Keys of the players sharing the keyboard, in the order of SPLIT_ACTIONS
+    split_keys: [[Key; 4]; 2]
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            bindings: ACTIONS.iter().map(|action| (*action, action.default_key())).collect(),
+            show_help: true,
+            language: None,
+            glass_width: DEFAULT_GLASS_WIDTH,
+            glass_height: DEFAULT_GLASS_HEIGHT,
+            piece_set: DEFAULT_PIECE_SET.to_string(),
+            split_garbage: true,
+            split_keys: DEFAULT_SPLIT_KEYS
+        }
+    }
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        storage::config_dir().map(|dir| dir.join(SETTINGS_FILE))
+    }
+
+    // Missing or broken settings fall back to the defaults
+    pub fn load() -> Self {
+        let mut settings = Settings::default();
+        let text = match Self::path().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(text) => text,
+            None => return settings
+        };
+
+        for (name, value) in storage::parse_key_values(&text) {
+            if name == "show_help" {
+                settings.show_help = value == "yes";
+            } else if name == "language" {
+                settings.language = Language::from_name(&value);
+            } else if name == "glass_width" {
+                if let Ok(width) = value.parse::<usize>() {
+                    settings.glass_width = width.clamp(*GLASS_WIDTH_RANGE.start(), *GLASS_WIDTH_RANGE.end());
+                }
+            } else if name == "glass_height" {
+                if let Ok(height) = value.parse::<usize>() {
+                    settings.glass_height = height.clamp(*GLASS_HEIGHT_RANGE.start(), *GLASS_HEIGHT_RANGE.end());
+                }
+            } else if name == "piece_set" {
+                settings.piece_set = value;
+            } else if name == "split_garbage" {
+                settings.split_garbage = value == "yes";
+            } else if let Some(action_name) = name.strip_prefix("key.") {
+                let action = ACTIONS.iter().find(|action| action.name() == action_name);
+                if let (Some(action), Some(key)) = (action, Key::from_name(&value)) {
+                    settings.set_key(*action, key);
+                }
+            } else if let Some((player, action_name)) = name.strip_prefix("key").and_then(|rest| rest.split_once('.')) {
+                let player = player.parse::<usize>().ok().filter(|player| (1..=2).contains(player));
+                let action = SPLIT_ACTIONS.iter().find(|action| action.name() == action_name);
+                if let (Some(player), Some(action), Some(key)) = (player, action, Key::from_name(&value)) {
+                    settings.set_split_key(player - 1, *action, key);
+                }
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let mut text = String::from("# rustris settings\n");
+        text += &format!("show_help = {}\n", if self.show_help {"yes"} else {"no"});
+        text += &format!("language = {}\n", self.language.map_or("auto", |language| language.name()));
+        text += &format!("glass_width = {}\nglass_height = {}\n", self.glass_width, self.glass_height);
+        text += &format!("piece_set = {}\n", self.piece_set);
+        text += &format!("split_garbage = {}\n", if self.split_garbage {"yes"} else {"no"});
+        for (action, key) in &self.bindings {
+            text += &format!("key.{} = {}\n", action.name(), key.name());
+        }
+        for (player, keys) in self.split_keys.iter().enumerate() {
+            for (action, key) in SPLIT_ACTIONS.iter().zip(keys) {
+                text += &format!("key{}.{} = {}\n", player + 1, action.name(), key.name());
+            }
+        }
+        if let Some(path) = Self::path() {
+            let _ = storage::write_atomic(&path, text.as_bytes());
+        }
+    }
+
+    // Makes the chosen language the one all texts are shown in
+    pub fn apply_language(&self) {
+        i18n::set_language(self.language.unwrap_or_else(Language::from_env));
+    }
+
+    // A set which is gone from the pieces file gives the standard pieces
+    pub fn piece_set(&self) -> PieceSet {
+        PieceSet::find(&self.piece_set).unwrap_or_else(PieceSet::standard)
+    }
+
+    // Steps through the known sets, around at the ends
+    fn next_piece_set(&mut self, step: isize) {
+        let names: Vec<String> = PieceSet::all().into_iter().map(|set| set.name).collect();
+        let current = names.iter().position(|name| *name == self.piece_set().name).unwrap_or(0);
+        let next = (current as isize + step).rem_euclid(names.len() as isize) as usize;
+        self.piece_set = names[next].clone();
+    }
+
+    pub fn key(&self, action: Action) -> Key {
+        self.bindings.iter()
+            .find(|(bound_action, _)| *bound_action == action)
+            .map_or(action.default_key(), |(_, key)| *key)
+    }
+
+    // A key belongs to one action only, the action which had it before
+    // gets the old key of the rebound one. Reserved keys are refused, and
+    // so are the keys of the two players for pause and quit. Returns true
+    // when the key is bound
+    pub fn set_key(&mut self, action: Action, key: Key) -> bool {
+        if is_reserved(key) {
+            return false;
+        }
+        let old_key = self.key(action);
+        let shared_key = if SHARED_ACTIONS.contains(&action) {
+            Some(key)
+        } else if SHARED_ACTIONS.iter().any(|shared| self.key(*shared) == key) {
+            Some(old_key)
+        } else {
+            None
+        };
+        if shared_key.is_some_and(|shared_key| self.split_action(shared_key).is_some()) {
+            return false;
+        }
+        for (bound_action, bound_key) in &mut self.bindings {
+            if *bound_action == action {
+                *bound_key = key;
+            } else if *bound_key == key {
+                *bound_key = old_key;
+            }
+        }
+        true
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        if let Some((_, action)) = RESERVED_KEYS.iter().find(|(reserved, _)| *reserved == key) {
+            return Some(*action);
+        }
+        self.bindings.iter()
+            .find(|(_, bound_key)| *bound_key == key)
+            .map(|(action, _)| *action)
+    }
+
+    pub fn split_key(&self, player: usize, action: Action) -> Option<Key> {
+        SPLIT_ACTIONS.iter().position(|split_action| *split_action == action).map(|idx| self.split_keys[player][idx])
+    }
+
+    // Player and action of a two player key
+    pub fn split_action(&self, key: Key) -> Option<(usize, Action)> {
+        self.split_keys.iter().enumerate().find_map(|(player, keys)| {
+            keys.iter().position(|bound_key| *bound_key == key).map(|idx| (player, SPLIT_ACTIONS[idx]))
+        })
+    }
+
+    // As set_key, among the keys of both players. The keys of pause and
+    // quit are refused
+    pub fn set_split_key(&mut self, player: usize, action: Action, key: Key) -> bool {
+        let idx = match SPLIT_ACTIONS.iter().position(|split_action| *split_action == action) {
+            Some(idx) => idx,
+            None => return false
+        };
+        if is_reserved(key) || SHARED_ACTIONS.iter().any(|shared| self.key(*shared) == key) {
+            return false;
+        }
+        let old_key = self.split_keys[player][idx];
+        for bound_key in self.split_keys.iter_mut().flatten() {
+            if *bound_key == key {
+                *bound_key = old_key;
+            }
+        }
+        self.split_keys[player][idx] = key;
+        true
+    }
+}
+
+// Binding waiting for a key in the settings screen, the keys of the two
+// players are asked for one after the other
+#[derive(Clone, Copy, PartialEq)]
+enum Binding {
+    Single(Action),
+    // Player and action
+    Split(usize, Action)
+}
+
+pub struct SettingsScreen {
+    menu: Menu,
+    rebinding: Option<Binding>
+}
+
+impl SettingsScreen {
+    pub fn new(settings: &Settings) -> Self {
+        let mut screen = SettingsScreen {menu: Menu::new(tr(Msg::Settings), Vec::new()), rebinding: None};
+        screen.update_items(settings);
+        screen
+    }
+
+    fn update_items(&mut self, settings: &Settings) {
+        let mut items: Vec<String> = ACTIONS.iter().map(|action| {
+            if self.rebinding == Some(Binding::Single(*action)) {
+                format!("{}: ?", action.title())
+            } else {
+                format!("{}: {}", action.title(), settings.key(*action).title())
+            }
+        }).collect();
+        items.extend(SPLIT_ACTIONS.iter().map(|action| {
+            let keys: Vec<String> = (0..2).map(|player| match settings.split_key(player, *action) {
+                _ if self.rebinding == Some(Binding::Split(player, *action)) => "?".to_string(),
+                Some(key) => key.title(),
+                None => String::new()
+            }).collect();
+            format!("{}: {}", tr_args(Msg::SplitAction, &[&action.title()]), keys.join(" "))
+        }));
+        items.push(format!("{}: {}", tr(Msg::Help), tr(if settings.show_help {Msg::Yes} else {Msg::No})));
+        let language = settings.language.map_or(tr(Msg::LanguageAuto), |language| language.title());
+        items.push(format!("{}: {}", tr(Msg::Language), language));
+        items.push(format!("{}: {}", tr(Msg::GlassWidth), settings.glass_width));
+        items.push(format!("{}: {}", tr(Msg::GlassHeight), settings.glass_height));
+        items.push(format!("{}: {}", tr(Msg::PieceSet), settings.piece_set().name.to_uppercase()));
+        items.push(format!("{}: {}", tr(Msg::SplitGarbage), tr(if settings.split_garbage {Msg::Yes} else {Msg::No})));
+        items.push(tr(Msg::Back).to_string());
+        self.menu.set_title(tr(Msg::Settings));
+        self.menu.set_items(items);
+    }
+
+    // Returns true when the screen is closed, settings are saved then
+    pub fn handle_key(&mut self, key: Key, settings: &mut Settings) -> bool {
+        if let Some(binding) = self.rebinding.take() {
+            self.rebinding = match binding {
+                // Esc keeps the old key
+                _ if key == Key::Esc => None,
+                // Refused keys leave the binding waiting on
+                Binding::Single(action) => (!settings.set_key(action, key)).then_some(binding),
+                // The second player is asked after the first one
+                Binding::Split(player, action) if settings.set_split_key(player, action, key) => {
+                    (player == 0).then_some(Binding::Split(1, action))
+                }
+                Binding::Split(_, _) => Some(binding)
+            };
+            self.update_items(settings);
+            return false;
+        }
+
+        let split_idx = ACTIONS.len();
+        let help_idx = split_idx + SPLIT_ACTIONS.len();
+        let language_idx = help_idx + 1;
+        let width_idx = language_idx + 1;
+        let height_idx = width_idx + 1;
+        let pieces_idx = height_idx + 1;
+        let garbage_idx = pieces_idx + 1;
+        match self.menu.handle_key(key, settings) {
+            MenuEvent::Select(idx) if idx < split_idx => {
+                self.rebinding = Some(Binding::Single(ACTIONS[idx]));
+            }
+            MenuEvent::Select(idx) if idx < help_idx => {
+                self.rebinding = Some(Binding::Split(0, SPLIT_ACTIONS[idx - split_idx]));
+            }
+            MenuEvent::Select(idx) | MenuEvent::Change(idx, _) if idx == help_idx => {
+                settings.show_help = !settings.show_help;
+            }
+            MenuEvent::Select(idx) | MenuEvent::Change(idx, _) if idx == language_idx => {
+                // Cycles through auto and every language
+                settings.language = match settings.language {
+                    None => Some(LANGUAGES[0]),
+                    Some(language) => LANGUAGES.iter()
+                        .position(|known| *known == language)
+                        .and_then(|idx| LANGUAGES.get(idx + 1))
+                        .copied()
+                };
+                settings.apply_language();
+            }
+            MenuEvent::Select(idx) | MenuEvent::Change(idx, _) if idx == garbage_idx => {
+                settings.split_garbage = !settings.split_garbage;
+            }
+            MenuEvent::Select(idx) if idx == pieces_idx => settings.next_piece_set(1),
+            MenuEvent::Change(idx, step) if idx == pieces_idx => settings.next_piece_set(step),
+            // Sizes change with left and right only
+            MenuEvent::Select(idx) if idx == width_idx || idx == height_idx => {}
+            MenuEvent::Select(_) | MenuEvent::Back | MenuEvent::Quit => {
+                settings.save();
+                return true;
+            }
+            MenuEvent::Change(idx, step) if idx == width_idx => {
+                settings.glass_width = settings.glass_width.saturating_add_signed(step)
+                    .clamp(*GLASS_WIDTH_RANGE.start(), *GLASS_WIDTH_RANGE.end());
+            }
+            MenuEvent::Change(idx, step) if idx == height_idx => {
+                settings.glass_height = settings.glass_height.saturating_add_signed(step)
+                    .clamp(*GLASS_HEIGHT_RANGE.start(), *GLASS_HEIGHT_RANGE.end());
+            }
+            MenuEvent::Change(_, _) | MenuEvent::None => {}
+        }
+        self.update_items(settings);
+        false
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas, center_x: usize, center_y: usize) {
+        self.menu.draw(canvas, center_x, center_y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_keys_are_not_bound() {
+        let mut settings = Settings::default();
+        settings.set_key(Action::Rotate, Key::Char(' '));
+        settings.set_key(Action::Quit, Key::Esc);
+        assert!(settings.key(Action::Rotate) == Action::Rotate.default_key());
+        assert!(settings.key(Action::Quit) == Action::Quit.default_key());
+        assert!(settings.action(Key::Char(' ')) == Some(Action::Drop));
+        assert!(settings.action(Key::Esc) == Some(Action::Pause));
+    }
+
+    #[test]
+    fn rebinding_waits_for_a_key_which_is_not_reserved() {
+        let mut settings = Settings::default();
+        let mut screen = SettingsScreen::new(&settings);
+        let rotate = ACTIONS.iter().position(|action| *action == Action::Rotate).unwrap();
+        screen.menu.selected = rotate;
+        screen.handle_key(Key::Enter, &mut settings);
+        screen.handle_key(Key::Char(' '), &mut settings);
+        assert!(screen.rebinding == Some(Binding::Single(Action::Rotate)));
+        screen.handle_key(Key::Char('x'), &mut settings);
+        assert!(screen.rebinding.is_none());
+        assert!(settings.action(Key::Char('x')) == Some(Action::Rotate));
+    }
+
+    #[test]
+    fn split_keys_swap_between_players() {
+        let mut settings = Settings::default();
+        assert!(settings.set_split_key(0, Action::Left, Key::Left));
+        assert!(settings.split_action(Key::Left) == Some((0, Action::Left)));
+        assert!(settings.split_action(Key::Char('a')) == Some((1, Action::Left)));
+        assert!(settings.split_key(1, Action::Left) == Some(Key::Char('a')));
+    }
+
+    #[test]
+    fn pause_and_quit_keep_keys_of_their_own() {
+        let mut settings = Settings::default();
+        assert!(!settings.set_split_key(1, Action::Drop, settings.key(Action::Pause)));
+        assert!(!settings.set_key(Action::Quit, Key::Char('w')));
+        // Left would hand its key over to quit
+        assert!(settings.set_key(Action::Left, Key::Char('a')));
+        assert!(!settings.set_key(Action::Left, settings.key(Action::Quit)));
+        assert!(settings.key(Action::Quit) == Action::Quit.default_key());
+    }
+
+    #[test]
+    fn both_players_are_asked_for_a_key() {
+        let mut settings = Settings::default();
+        let mut screen = SettingsScreen::new(&settings);
+        screen.menu.selected = ACTIONS.len();
+        screen.handle_key(Key::Enter, &mut settings);
+        screen.handle_key(Key::Char('j'), &mut settings);
+        assert!(screen.rebinding == Some(Binding::Split(1, Action::Left)));
+        screen.handle_key(Key::Char('p'), &mut settings);
+        screen.handle_key(Key::Char('k'), &mut settings);
+        assert!(screen.rebinding.is_none());
+        assert!(settings.split_action(Key::Char('j')) == Some((0, Action::Left)));
+        assert!(settings.split_action(Key::Char('k')) == Some((1, Action::Left)));
+    }
+}
@@ -0,0 +1,393 @@
+// Spectators of a game. A game started with a broadcast port sends its
+// glasses to everybody connected to the port on this machine, a spectator
+// shows them the way the players see them and sends nothing back but its
+// hello. See net.rs for the messages
+use crate::field;
+use crate::game::{GAME_CANVAS_HEIGHT, GAME_CANVAS_WIDTH, GLASS_HEIGHT_RANGE, GLASS_WIDTH_RANGE};
+use crate::i18n::{tr, tr_args, Msg};
+use crate::ioscreen::{self, text_width, Color, Key};
+use crate::layout::Layout;
+use crate::menu;
+use crate::net::{self, Connection, Message, PROTOCOL_VERSION};
+use crate::settings::{Action, Settings};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+// How long the spectator waits for a key before looking for new messages
+const WATCH_POLL_MSEC: u64 = 10;
+const METER_COLOR: Color = Color::Red;
+
+// What the game is busy with, as told in STATE messages
+#[derive(Clone, Copy, PartialEq)]
+pub enum ViewState {
+    // Between games, the glasses of the last one stay
+    Idle,
+    Playing,
+    Paused,
+    Over
+}
+
+impl ViewState {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Playing => "playing",
+            Self::Paused => "paused",
+            Self::Over => "over"
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Idle, Self::Playing, Self::Paused, Self::Over].into_iter().find(|state| state.name() == name)
+    }
+}
+
+// Glass of one player as spectators see it
+pub struct Board {
+    pub name: String,
+    pub lines: u32,
+    // Garbage rows on their way to the glass
+    pub incoming: u32,
+    // As in Field::rows
+    pub rows: Vec<String>,
+    // The glass overflowed
+    pub out: bool
+}
+
+pub struct Broadcast {
+    listener: TcpListener,
+    name: String,
+    // Spectators until they say hello, and the ones watching
+    joining: Vec<Connection>,
+    spectators: Vec<Connection>,
+    // The view as last sent, new spectators get all of it
+    sent: Vec<Message>
+}
+
+impl Broadcast {
+    // Spectators are taken on the port of the loopback interface only
+    pub fn new(port: u16, name: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Broadcast {listener, name: name.to_string(), joining: Vec::new(), spectators: Vec::new(), sent: Vec::new()})
+    }
+
+    // Name of the player at this keyboard
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn poll_spectators(&mut self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            let connection = stream.set_nonblocking(false).and_then(|_| Connection::new(stream));
+            if let Ok(connection) = connection {
+                self.joining.push(connection);
+            }
+        }
+        for mut connection in std::mem::take(&mut self.joining) {
+            match connection.receive().into_iter().next() {
+                Some(Message::Watch {version}) if version == PROTOCOL_VERSION => {
+                    for message in &self.sent {
+                        connection.send(message);
+                    }
+                    self.spectators.push(connection);
+                }
+                Some(Message::Watch {..}) => connection.send(&Message::Refuse {reason: "version".to_string()}),
+                None if !connection.is_closed() => self.joining.push(connection),
+                _ => {}
+            }
+        }
+        // Whatever spectators say after their hello is of no interest
+        for spectator in &mut self.spectators {
+            spectator.receive();
+        }
+        self.spectators.retain(|spectator| !spectator.is_closed());
+    }
+
+    fn send_all(&mut self, message: &Message) {
+        for spectator in &mut self.spectators {
+            spectator.send(message);
+        }
+    }
+
+    // Called once a screen frame, only what changed since the last call
+    // goes out
+    pub fn update(&mut self, state: ViewState, glass_size: (usize, usize), boards: &[Board]) {
+        self.poll_spectators();
+        let players = boards.iter().enumerate()
+            .map(|(id, board)| (id, net::clean_name(&board.name), board.out))
+            .collect();
+        let mut view = vec![
+            Message::View {glass_size, players},
+            Message::State {state: state.name().to_string()}
+        ];
+        view.extend(boards.iter().enumerate().map(|(id, board)| Message::Snapshot {
+            id,
+            lines: board.lines,
+            incoming: board.incoming,
+            rows: board.rows.clone()
+        }));
+        // Spectators start over with a new view, the glasses go out again
+        let new_view = self.sent.first() != view.first();
+        for (idx, message) in view.iter().enumerate() {
+            if new_view || self.sent.get(idx) != Some(message) {
+                self.send_all(message);
+            }
+        }
+        self.sent = view;
+    }
+
+    // Between games, the glasses of the last one stay on view
+    pub fn idle(&mut self) {
+        self.poll_spectators();
+        let idle = Message::State {state: ViewState::Idle.name().to_string()};
+        match self.sent.iter().position(|message| matches!(message, Message::State {..})) {
+            Some(idx) if self.sent[idx] == idle => {}
+            Some(idx) => {
+                self.sent[idx] = idle.clone();
+                self.send_all(&idle);
+            }
+            None => {
+                self.sent.push(idle.clone());
+                self.send_all(&idle);
+            }
+        }
+    }
+}
+
+pub struct Spectator {
+    connection: Connection,
+    settings: Settings,
+    // None until the game tells what it is busy with
+    state: Option<ViewState>,
+    glass_size: (usize, usize),
+    boards: Vec<Board>,
+    // The view can not go on, the text says why
+    closed: Option<String>,
+    screen_canvas: ioscreen::Canvas,
+    layouts: Vec<Layout>
+}
+
+impl Spectator {
+    // Connects to a game broadcasting on the port of this machine
+    pub fn watch(settings: Settings, port: u16) -> io::Result<Self> {
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let stream = TcpStream::connect_timeout(&address, Duration::from_secs(CONNECT_TIMEOUT_SECS))?;
+        let mut connection = Connection::new(stream)?;
+        connection.send(&Message::Watch {version: PROTOCOL_VERSION});
+        let (width, height) = ioscreen::screen_size().unwrap_or((GAME_CANVAS_WIDTH, GAME_CANVAS_HEIGHT));
+        Ok(Spectator {
+            connection,
+            settings,
+            state: None,
+            glass_size: (0, 0),
+            boards: Vec::new(),
+            closed: None,
+            screen_canvas: ioscreen::Canvas::new(width, height),
+            layouts: Vec::new()
+        })
+    }
+
+    fn update_layouts(&mut self) {
+        let (width, height) = self.glass_size;
+        self.layouts = Layout::side_by_side(self.screen_canvas.width(), self.screen_canvas.height(),
+                                            width, height, self.boards.len());
+    }
+
+    fn handle_resize(&mut self) {
+        if let Some((width, height)) = ioscreen::take_resize() {
+            self.screen_canvas = ioscreen::Canvas::new(width, height);
+            self.update_layouts();
+            ioscreen::clear_screen();
+        }
+    }
+
+    fn poll(&mut self) {
+        for message in self.connection.receive() {
+            match message {
+                // Layouts hold for the glasses a game can have only
+                Message::View {glass_size: (width, height), ..}
+                    if !GLASS_WIDTH_RANGE.contains(&width) || !GLASS_HEIGHT_RANGE.contains(&height) => {
+                    self.connection.close();
+                    self.closed = Some(tr_args(Msg::NoGlassSize, &[&width, &height]));
+                    break;
+                }
+                Message::View {glass_size, players} => {
+                    // Snapshots of the glasses follow
+                    self.glass_size = glass_size;
+                    self.boards = players.into_iter()
+                        .map(|(_, name, out)| Board {name, lines: 0, incoming: 0, rows: Vec::new(), out})
+                        .collect();
+                    self.update_layouts();
+                }
+                Message::State {state} => self.state = ViewState::from_name(&state).or(self.state),
+                Message::Snapshot {id, lines, incoming, rows} => {
+                    if let Some(board) = self.boards.get_mut(id) {
+                        board.lines = lines;
+                        board.incoming = incoming;
+                        board.rows = rows;
+                    }
+                }
+                Message::Refuse {reason} => {
+                    let text = if reason == "version" {tr(Msg::OtherVersion).to_string()} else {reason};
+                    self.closed = Some(text);
+                }
+                _ => {}
+            }
+        }
+        if self.connection.is_closed() && self.closed.is_none() {
+            self.closed = Some(tr(Msg::ConnectionLost).to_string());
+        }
+    }
+
+    // Returns false when the spectator leaves
+    fn handle_key(&mut self, key: Key) -> bool {
+        self.closed.is_none() && key != Key::Esc && self.settings.action(key) != Some(Action::Quit)
+    }
+
+    fn draw_panel(&mut self, lines: &[String]) {
+        let center_x = self.screen_canvas.width() / 2;
+        let center_y = self.screen_canvas.height() / 2;
+        menu::draw_panel(&mut self.screen_canvas, lines, center_x, center_y);
+    }
+
+    fn draw_boards(&mut self) {
+        let (width, height) = self.glass_size;
+        for (board, layout) in self.boards.iter().zip(&self.layouts) {
+            let (left, top) = (layout.glass_left, layout.glass_top);
+            field::draw_border(&mut self.screen_canvas, left, top, width, height);
+            field::draw_rows(&mut self.screen_canvas, &board.rows, left, top);
+            let title = if board.out {
+                format!("{} {}", board.name, tr(Msg::Out))
+            } else {
+                format!("{} {}", board.name, board.lines)
+            };
+            self.screen_canvas.draw_text(&title, left, top - 1);
+            // Incoming rows fill the meter up from the bottom of the glass
+            for y in 0..(board.incoming as usize).min(height) {
+                self.screen_canvas.set_colored_char(layout.meter_x(), top + height - 1 - y, '█', Some(METER_COLOR));
+            }
+        }
+    }
+
+    fn draw_too_small(&mut self) {
+        let (width, height) = self.glass_size;
+        let (min_width, min_height) = Layout::side_by_side_min_size(width, height, self.boards.len());
+        let too_small = tr(Msg::TooSmall);
+        let need_size = tr_args(Msg::NeedSize, &[&min_width, &min_height]);
+        let center_x = self.screen_canvas.width() / 2;
+        let center_y = self.screen_canvas.height() / 2;
+        self.screen_canvas.draw_text(too_small, center_x.saturating_sub(text_width(too_small) / 2), center_y.saturating_sub(1));
+        self.screen_canvas.draw_text(&need_size, center_x.saturating_sub(text_width(&need_size) / 2), center_y);
+    }
+
+    // Lines of the panel over the glasses, none while the game goes on
+    fn status_lines(&self) -> Vec<String> {
+        let status = match self.state {
+            None => tr(Msg::Connecting).to_string(),
+            Some(ViewState::Playing) => return Vec::new(),
+            Some(ViewState::Idle) => tr(Msg::WaitingForGame).to_string(),
+            Some(ViewState::Paused) => tr(Msg::Pause).to_string(),
+            Some(ViewState::Over) if self.boards.len() < 2 => tr(Msg::GameOver).to_string(),
+            Some(ViewState::Over) => {
+                let mut left = self.boards.iter().filter(|board| !board.out);
+                match (left.next(), left.next()) {
+                    (Some(winner), None) => tr_args(Msg::NamedWinner, &[&winner.name]),
+                    _ => tr(Msg::Draw).to_string()
+                }
+            }
+        };
+        let quit = format!("{}: {}", self.settings.key(Action::Quit).title(), Action::Quit.title());
+        vec![tr(Msg::Spectating).to_string(), String::new(), status, String::new(), quit]
+    }
+
+    fn draw(&mut self) {
+        if let Some(text) = &self.closed {
+            let lines = [tr(Msg::Spectating).to_string(), String::new(), text.clone(), String::new(),
+                         tr(Msg::AnyKeyToExit).to_string()];
+            self.draw_panel(&lines);
+        } else if self.layouts.first().is_some_and(|layout| layout.too_small) {
+            self.draw_too_small();
+        } else {
+            self.draw_boards();
+            let lines = self.status_lines();
+            if !lines.is_empty() {
+                self.draw_panel(&lines);
+            }
+        }
+        self.screen_canvas.display();
+    }
+
+    // Runs until the spectator leaves
+    pub fn game_loop(&mut self) {
+        ioscreen::clear_screen();
+        loop {
+            if ioscreen::quit_requested() {
+                return;
+            }
+            self.handle_resize();
+            self.poll();
+            self.screen_canvas.clear();
+            self.draw();
+            if let Some(key) = ioscreen::getch(Duration::from_millis(WATCH_POLL_MSEC)) {
+                if !self.handle_key(key) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::thread;
+    use std::time::Instant;
+
+    // Polls the spectator until the condition holds
+    fn wait_for(spectator: &mut Spectator, mut done: impl FnMut(&mut Spectator) -> bool) {
+        let start = Instant::now();
+        while !done(spectator) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(5));
+            spectator.poll();
+        }
+    }
+
+    #[test]
+    fn spectators_see_the_glasses_broadcast() {
+        let mut broadcast = Broadcast::new(0, "ann").unwrap();
+        let port = broadcast.listener.local_addr().unwrap().port();
+        let mut spectator = Spectator::watch(Settings::default(), port).unwrap();
+        let boards = [
+            Board {name: "ann".to_string(), lines: 3, incoming: 2, rows: vec!["....".to_string(); 10], out: false},
+            Board {name: "cpu".to_string(), lines: 1, incoming: 0, rows: vec!["#..#".to_string(); 10], out: true}
+        ];
+        wait_for(&mut spectator, |spectator| {
+            broadcast.update(ViewState::Over, (4, 10), &boards);
+            spectator.state == Some(ViewState::Over) && spectator.boards.iter().all(|board| !board.rows.is_empty())
+        });
+        assert_eq!(spectator.glass_size, (4, 10));
+        assert_eq!(spectator.boards.len(), boards.len());
+        for (seen, board) in spectator.boards.iter().zip(&boards) {
+            assert_eq!(seen.name, board.name);
+            assert_eq!((seen.lines, seen.incoming, seen.out), (board.lines, board.incoming, board.out));
+            assert_eq!(seen.rows, board.rows);
+        }
+    }
+
+    #[test]
+    fn views_of_glasses_too_large_close_the_connection() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut spectator = Spectator::watch(Settings::default(), listener.local_addr().unwrap().port()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"VIEW 100 20 0,ann,0\nSTATE playing\n").unwrap();
+        wait_for(&mut spectator, |spectator| spectator.closed.is_some());
+        assert_eq!(spectator.closed.as_deref(), Some(tr_args(Msg::NoGlassSize, &[&100, &20]).as_str()));
+        assert!(spectator.connection.is_closed());
+        assert!(spectator.boards.is_empty());
+    }
+}
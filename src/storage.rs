@@ -0,0 +1,87 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const APP_DIR: &str = "rustris";
+
+fn env_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from)
+}
+
+#[cfg(target_os = "windows")]
+pub fn config_dir() -> Option<PathBuf> {
+    env_dir("APPDATA").map(|dir| dir.join(APP_DIR))
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn config_dir() -> Option<PathBuf> {
+    env_dir("XDG_CONFIG_HOME")
+        .or_else(|| env_dir("HOME").map(|home| home.join(".config")))
+        .map(|dir| dir.join(APP_DIR))
+}
+
+#[cfg(target_os = "windows")]
+pub fn data_dir() -> Option<PathBuf> {
+    env_dir("LOCALAPPDATA").or_else(|| env_dir("APPDATA")).map(|dir| dir.join(APP_DIR))
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn data_dir() -> Option<PathBuf> {
+    env_dir("XDG_DATA_HOME")
+        .or_else(|| env_dir("HOME").map(|home| home.join(".local").join("share")))
+        .map(|dir| dir.join(APP_DIR))
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+// YYYY-MM-DD of a unix time, UTC
+pub fn format_date(unix_time: u64) -> String {
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let days = (unix_time / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {month_index + 3} else {month_index - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// M:SS.T of a duration, tenths matter when games are raced against the clock
+pub fn format_duration(duration_ms: u64) -> String {
+    let tenths = duration_ms / 100;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+// Writes into a temporary file next to the target and renames it over the
+// target, so a crash never leaves a half written file behind
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)
+}
+
+// Lines of "name = value", empty lines and lines starting with # are skipped
+pub fn parse_key_values(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
@@ -0,0 +1,420 @@
+// Versus games against the computer or between two players at one
+// keyboard. Both glasses get the same pieces, rows cleared in one glass come
+// up from the bottom of the other one as garbage
+use crate::ai::{Bot, Weights};
+use crate::field::Field;
+use crate::garbage::Garbage;
+use crate::game::{GAME_CANVAS_HEIGHT, GAME_CANVAS_WIDTH, LOGIC_RATE_HZ};
+use crate::gravity::GravityCurve;
+use crate::i18n::{tr, tr_args, Msg};
+use crate::ioscreen::{self, pad_left, pad_right, text_width, Color, Key};
+use crate::layout::Layout;
+use crate::menu::{self, Menu, MenuEvent};
+use crate::random;
+use crate::settings::{Action, Settings};
+use crate::spectate::{Board, Broadcast, ViewState};
+use crate::storage;
+use std::time::{Duration, Instant};
+
+// Logic frames the computer waits between its moves
+const CPU_STEP_FRAMES: u32 = 10;
+const GRAVITY_CURVE: GravityCurve = GravityCurve::Guideline;
+const METER_COLOR: Color = Color::Red;
+const MAX_CATCH_UP_FRAMES: u32 = 10;
+
+const PAUSE_ITEMS: [Msg; 3] = [Msg::Resume, Msg::Restart, Msg::Quit];
+const PAUSE_RESUME: usize = 0;
+const PAUSE_RESTART: usize = 1;
+const PAUSE_QUIT: usize = 2;
+
+// Who plays in the second glass, the first one is always played from the
+// keyboard
+#[derive(Clone, Copy, PartialEq)]
+pub enum Opponent {
+    Cpu,
+    // Second player at the same keyboard, the glasses get keys of their own
+    Player
+}
+
+struct Player {
+    field: Field,
+    // None for a player at the keyboard
+    bot: Option<Bot>,
+    // Moves of the shape waiting for the next logic frame
+    pending_actions: Vec<Action>,
+    garbage: Garbage,
+    // Garbage rows sent to the other glass
+    sent: u32
+}
+
+impl Player {
+    fn new(field: Field, bot: Option<Bot>, seed: u64) -> Self {
+        Player {field, bot, pending_actions: Vec::new(), garbage: Garbage::new(seed), sent: 0}
+    }
+}
+
+enum VersusState {
+    Playing,
+    Paused(Menu),
+    // Index of the player who won
+    Over(usize)
+}
+
+pub struct Versus {
+    state: VersusState,
+    settings: Settings,
+    opponent: Opponent,
+    // Cleared rows go over to the other glass, two players may go without
+    garbage: bool,
+    players: [Player; 2],
+    seed: u64,
+    // Logic frames played, pauses are not counted
+    frames: u64,
+    screen_canvas: ioscreen::Canvas,
+    layouts: Vec<Layout>,
+    // Spectators watching both glasses
+    broadcast: Option<Broadcast>
+}
+
+impl Versus {
+    pub fn new(opponent: Opponent, settings: Settings) -> Self {
+        let glass_size = (settings.glass_width, settings.glass_height);
+        let (width, height) = ioscreen::screen_size().unwrap_or((GAME_CANVAS_WIDTH, GAME_CANVAS_HEIGHT));
+        let seed = random::new_seed();
+        Versus {
+            state: VersusState::Playing,
+            players: Self::new_players(opponent, &settings, seed),
+            garbage: opponent == Opponent::Cpu || settings.split_garbage,
+            settings,
+            opponent,
+            seed,
+            frames: 0,
+            screen_canvas: ioscreen::Canvas::new(width, height),
+            layouts: Layout::side_by_side(width, height, glass_size.0, glass_size.1, 2),
+            broadcast: None
+        }
+    }
+
+    // The same seed on both sides, nobody gets better pieces
+    fn new_players(opponent: Opponent, settings: &Settings, seed: u64) -> [Player; 2] {
+        let glass_size = (settings.glass_width, settings.glass_height);
+        let pieces = settings.piece_set();
+        let field = || Field::new(pieces.clone(), seed, glass_size, GRAVITY_CURVE);
+        let bot = match opponent {
+            Opponent::Cpu => Some(Bot::new(Weights::load(), CPU_STEP_FRAMES)),
+            Opponent::Player => None
+        };
+        [Player::new(field(), None, seed), Player::new(field(), bot, seed)]
+    }
+
+    fn restart(&mut self) {
+        self.seed = random::new_seed();
+        self.players = Self::new_players(self.opponent, &self.settings, self.seed);
+        let glass = &self.players[0].field.glass;
+        self.layouts = Layout::side_by_side(self.layouts[0].canvas_width, self.layouts[0].canvas_height,
+                                            glass.width(), glass.height(), 2);
+        self.frames = 0;
+        self.state = VersusState::Playing;
+    }
+
+    fn handle_resize(&mut self) {
+        if let Some((width, height)) = ioscreen::take_resize() {
+            let glass = &self.players[0].field.glass;
+            self.layouts = Layout::side_by_side(width, height, glass.width(), glass.height(), 2);
+            self.screen_canvas = ioscreen::Canvas::new(width, height);
+            ioscreen::clear_screen();
+        }
+    }
+
+    fn pause(&mut self, selected: usize) {
+        let mut menu = Menu::new(tr(Msg::Pause), PAUSE_ITEMS.iter().map(|item| tr(*item).to_string()).collect());
+        menu.selected = selected;
+        self.state = VersusState::Paused(menu);
+        for player in &mut self.players {
+            player.pending_actions.clear();
+        }
+    }
+
+    // Player the key moves the shape of and how, two players have keys of
+    // their own and the single one has the usual keys. Pause and quit have
+    // the usual keys either way
+    fn player_action(&self, key: Key) -> Option<(usize, Action)> {
+        match (self.opponent, self.settings.action(key)) {
+            (Opponent::Cpu, action) => action.map(|action| (0, action)),
+            (Opponent::Player, Some(action @ (Action::Pause | Action::Quit))) => Some((0, action)),
+            (Opponent::Player, _) => self.settings.split_action(key)
+        }
+    }
+
+    // Returns false when the game should be left
+    fn handle_key(&mut self, key: Key) -> bool {
+        match &mut self.state {
+            VersusState::Playing => match self.player_action(key) {
+                Some((_, Action::Pause)) => self.pause(PAUSE_RESUME),
+                // Quitting goes through the pause menu as a confirmation
+                Some((_, Action::Quit)) => self.pause(PAUSE_QUIT),
+                Some((idx, action)) => self.players[idx].pending_actions.push(action),
+                None => {}
+            },
+            VersusState::Paused(menu) => match menu.handle_key(key, &self.settings) {
+                MenuEvent::Select(PAUSE_RESUME) | MenuEvent::Back => self.state = VersusState::Playing,
+                MenuEvent::Select(PAUSE_RESTART) => self.restart(),
+                MenuEvent::Select(PAUSE_QUIT) => return false,
+                MenuEvent::Quit if menu.selected == PAUSE_QUIT => return false,
+                MenuEvent::Quit => menu.selected = PAUSE_QUIT,
+                _ => {}
+            },
+            VersusState::Over(_) => {
+                if key == Key::Char(' ') {
+                    self.restart();
+                } else if self.settings.action(key) == Some(Action::Quit) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn logic_frame(&mut self) {
+        self.frames += 1;
+        for idx in 0..self.players.len() {
+            let player = &mut self.players[idx];
+            let actions = match &mut player.bot {
+                Some(bot) => {
+                    let field = &player.field;
+                    bot.actions(&field.glass, field.piece(), field.position(), field.score.pieces)
+                }
+                None => std::mem::take(&mut player.pending_actions)
+            };
+            let events = player.field.update(&actions);
+            let mut topped_out = events.topped_out;
+            if events.locked && !topped_out && self.garbage {
+                let (attack, overflowed) = player.garbage.shape_locked(&mut player.field, events.rows_cleared);
+                player.sent += attack;
+                topped_out = overflowed;
+                self.players[1 - idx].garbage.push(attack);
+            }
+            if topped_out {
+                self.state = VersusState::Over(1 - idx);
+                return;
+            }
+        }
+    }
+
+    fn draw_too_small(&mut self) {
+        let glass = &self.players[0].field.glass;
+        let (min_width, min_height) = Layout::side_by_side_min_size(glass.width(), glass.height(), 2);
+        let too_small = tr(Msg::TooSmall);
+        let need_size = tr_args(Msg::NeedSize, &[&min_width, &min_height]);
+        let center_x = self.screen_canvas.width() / 2;
+        let center_y = self.screen_canvas.height() / 2;
+        self.screen_canvas.draw_text(too_small, center_x.saturating_sub(text_width(too_small) / 2), center_y.saturating_sub(1));
+        self.screen_canvas.draw_text(&need_size, center_x.saturating_sub(text_width(&need_size) / 2), center_y);
+        self.screen_canvas.display();
+    }
+
+    fn player_name(&self, idx: usize) -> String {
+        match (self.opponent, idx) {
+            (Opponent::Cpu, 0) => tr(Msg::You).to_string(),
+            (Opponent::Cpu, _) => tr(Msg::Cpu).to_string(),
+            (Opponent::Player, _) => tr_args(Msg::PlayerNumber, &[&(idx + 1)])
+        }
+    }
+
+    // Glass contents are hidden while the game is paused
+    fn draw_players(&mut self, with_contents: bool) {
+        let with_shape = matches!(self.state, VersusState::Playing);
+        for (idx, (player, layout)) in self.players.iter().zip(&self.layouts).enumerate() {
+            let (left, top) = (layout.glass_left, layout.glass_top);
+            player.field.draw_border(&mut self.screen_canvas, left, top);
+            if with_contents {
+                player.field.draw(&mut self.screen_canvas, left, top, with_shape);
+            }
+
+            let title = format!("{} {}", self.player_name(idx), player.field.score.lines);
+            self.screen_canvas.draw_text(&title, left, top - 1);
+            // Incoming rows fill the meter up from the bottom of the glass
+            let height = player.field.glass.height();
+            for y in 0..(player.garbage.rows() as usize).min(height) {
+                self.screen_canvas.set_colored_char(layout.meter_x(), top + height - 1 - y, '█', Some(METER_COLOR));
+            }
+        }
+    }
+
+    fn summary_lines(&self, winner: usize) -> Vec<String> {
+        let title = match (self.opponent, winner) {
+            (Opponent::Cpu, 0) => tr(Msg::YouWin).to_string(),
+            (Opponent::Cpu, _) => tr(Msg::YouLose).to_string(),
+            (Opponent::Player, _) => tr_args(Msg::PlayerWins, &[&(winner + 1)])
+        };
+        // A column for every player, labels differ in width between
+        // languages and values stay aligned
+        let row = |label: &str, values: Vec<String>| {
+            values.iter().fold(pad_right(label, 12), |line, value| line + &pad_left(value, 11))
+        };
+        let stat = |msg: Msg, value: fn(&Player) -> u32| row(tr(msg), self.players.iter().map(|player| value(player).to_string()).collect());
+        vec![
+            title,
+            String::new(),
+            row("", (0..self.players.len()).map(|idx| self.player_name(idx)).collect()),
+            stat(Msg::Lines, |player| player.field.score.lines),
+            stat(Msg::Sent, |player| player.sent),
+            stat(Msg::Pieces, |player| player.field.score.pieces),
+            String::new(),
+            format!("{} {}", pad_right(tr(Msg::Time), 12), storage::format_duration(self.frames * 1000 / LOGIC_RATE_HZ)),
+            format!("{} {:016x}", tr(Msg::Seed), self.seed),
+            String::new(),
+            tr(Msg::PressSpace).to_string(),
+        ]
+    }
+
+    fn draw(&mut self) {
+        let center_x = self.screen_canvas.width() / 2;
+        let center_y = self.screen_canvas.height() / 2;
+        self.draw_players(!matches!(self.state, VersusState::Paused(_)));
+        match &self.state {
+            VersusState::Playing => {}
+            VersusState::Paused(menu) => menu.draw(&mut self.screen_canvas, center_x, center_y),
+            VersusState::Over(winner) => {
+                let lines = self.summary_lines(*winner);
+                menu::draw_panel(&mut self.screen_canvas, &lines, center_x, center_y);
+            }
+        }
+        self.screen_canvas.display();
+    }
+
+    // The broadcast goes from game to game, the caller keeps it in between
+    pub fn set_broadcast(&mut self, broadcast: Option<Broadcast>) {
+        self.broadcast = broadcast;
+    }
+
+    pub fn take_broadcast(&mut self) -> Option<Broadcast> {
+        self.broadcast.take()
+    }
+
+    // Spectators see the player at the keyboard by name rather than as you
+    fn update_broadcast(&mut self) {
+        let own_name = match &self.broadcast {
+            Some(broadcast) => broadcast.name().to_string(),
+            None => return
+        };
+        let (state, winner) = match self.state {
+            VersusState::Playing => (ViewState::Playing, None),
+            VersusState::Paused(_) => (ViewState::Paused, None),
+            VersusState::Over(winner) => (ViewState::Over, Some(winner))
+        };
+        let boards: Vec<Board> = self.players.iter().enumerate().map(|(idx, player)| Board {
+            name: if (self.opponent, idx) == (Opponent::Cpu, 0) {own_name.clone()} else {self.player_name(idx)},
+            lines: player.field.score.lines,
+            incoming: player.garbage.rows(),
+            rows: player.field.rows(state == ViewState::Playing),
+            out: winner.is_some_and(|winner| winner != idx)
+        }).collect();
+        let glass = &self.players[0].field.glass;
+        let glass_size = (glass.width(), glass.height());
+        if let Some(broadcast) = &mut self.broadcast {
+            broadcast.update(state, glass_size, &boards);
+        }
+    }
+
+    // Runs until the player leaves the game
+    pub fn game_loop(&mut self) {
+        ioscreen::clear_screen();
+        let frame_duration = Duration::from_nanos(1_000_000_000 / LOGIC_RATE_HZ);
+        let mut next_frame = Instant::now();
+
+        loop {
+            // Keys are collected while waiting for the next logic frame
+            let now = Instant::now();
+            if now < next_frame {
+                if let Some(key) = ioscreen::getch(next_frame - now) {
+                    if !self.handle_key(key) {
+                        return;
+                    }
+                }
+                continue;
+            }
+
+            if ioscreen::quit_requested() {
+                return;
+            }
+
+            self.handle_resize();
+            let too_small = self.layouts[0].too_small;
+            let mut frames = 0;
+            while next_frame <= now && frames < MAX_CATCH_UP_FRAMES {
+                if let (VersusState::Playing, false) = (&self.state, too_small) {
+                    self.logic_frame();
+                }
+                next_frame += frame_duration;
+                frames += 1;
+            }
+            if next_frame <= now {
+                next_frame = now + frame_duration;
+            }
+
+            self.screen_canvas.clear();
+            if too_small {
+                self.draw_too_small();
+            } else {
+                self.draw();
+            }
+            self.update_broadcast();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::ShapeState;
+
+    // The first player drops an I into a well four rows deep
+    fn tetris_ready(versus: &mut Versus) {
+        let field = &mut versus.players[0].field;
+        for y in 16..20 {
+            for x in 1..10 {
+                field.glass.fill(x, y);
+            }
+        }
+        field.shape = ShapeState {piece: field.pieces.piece_index("I").unwrap(), position: 0, x: 0, y: 0};
+        versus.players[0].pending_actions.push(Action::Drop);
+    }
+
+    #[test]
+    fn cleared_rows_go_over_to_the_other_glass() {
+        let mut versus = Versus::new(Opponent::Player, Settings::default());
+        tetris_ready(&mut versus);
+        versus.logic_frame();
+        assert_eq!(versus.players[0].sent, 4);
+        assert_eq!(versus.players[1].garbage.rows(), 4);
+        assert_eq!(versus.players[1].field.garbage_rows(), 0);
+
+        // They come up once a shape of the other glass locks
+        versus.players[1].pending_actions.push(Action::Drop);
+        versus.logic_frame();
+        assert_eq!(versus.players[1].garbage.rows(), 0);
+        assert_eq!(versus.players[1].field.garbage_rows(), 4);
+    }
+
+    #[test]
+    fn attacks_cancel_the_garbage_coming_to_the_attacker() {
+        let mut versus = Versus::new(Opponent::Player, Settings::default());
+        versus.players[0].garbage.push(3);
+        tetris_ready(&mut versus);
+        versus.logic_frame();
+        assert_eq!(versus.players[0].garbage.rows(), 0);
+        assert_eq!(versus.players[0].sent, 1);
+        assert_eq!(versus.players[1].garbage.rows(), 1);
+    }
+
+    #[test]
+    fn two_players_may_go_without_garbage() {
+        let mut settings = Settings::default();
+        settings.split_garbage = false;
+        let mut versus = Versus::new(Opponent::Player, settings);
+        tetris_ready(&mut versus);
+        versus.logic_frame();
+        assert_eq!(versus.players[1].garbage.rows(), 0);
+    }
+}